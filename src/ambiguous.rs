@@ -0,0 +1,966 @@
+use crate::cst::{Node, Token};
+use crate::indent::{check_indent, indented, with_reference, Block, IndentedBlock, WithPos};
+use crate::position::{anchored, anchored_text, offset_of, position_of, with_origin};
+use crate::state::{GetState, SetState, UpdateState};
+use crate::trace::Trace;
+use crate::trivia::{BlockComment, LineComment};
+use crate::{
+    AndThen, Any, ChainLeft1, ChainRight1, CheckedInteger, Choice, Count, DepthGuard, Eof, Epsilon,
+    Float, Function, Identifier, Keyword, Label, Left, Locate, Located, LookAhead, Many, Many1,
+    ManyTill, Map, MaxDepth, Not, Optional, Or, OrDefault, Origin, Parser, Permutation, Pratt,
+    Recognize, Repeat, Right, Satisfy, SeparatedBy, SeparatedEndBy, SkipOptional, SkipUntil, Span,
+    Spanned, Str, StrNoCase, StringLiteral, TakeUntil, TakeWhile, Trivia, Whitespaces, DEPTH,
+};
+use std::borrow::Cow;
+
+// 来自Hutton和Meijer的list of successes：失败是空列表，有歧义的时候每一种解析都在列表里
+
+/// a parser that returns every way it can match, not only the first one
+///
+/// not implemented for syntax_tree, which builds one tree from one parse. State changed by set_state or update_state
+/// in one alternative is still there when the next alternative is tried.
+pub trait AllParser<'a, T> {
+    /// every (result, remaining input), an empty Vec if no match
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)>;
+
+    /// results of parses that consume the whole input
+    fn parse_complete(&self, input: &'a str) -> Vec<T> {
+        self.parse_all(input)
+            .into_iter()
+            .filter(|(_, remaining)| remaining.is_empty())
+            .map(|(a, _)| a)
+            .collect()
+    }
+}
+
+// 本来就只有一种结果的parser
+macro_rules! impl_deterministic {
+    ($([$($g:tt)*] $ty:ty => $t:ty),* $(,)?) => {$(
+        impl<'a, $($g)*> AllParser<'a, $t> for $ty
+        where
+            $ty: Parser<'a, $t>,
+        {
+            fn parse_all(&self, input: &'a str) -> Vec<($t, &'a str)> {
+                Parser::parse(self, input).into_iter().collect()
+            }
+        }
+    )*};
+}
+
+impl_deterministic! {
+    [] Any => char,
+    [] Eof => (),
+    [] Epsilon => (),
+    [F] Satisfy<F> => char,
+    [] crate::Char => char,
+    [] Whitespaces => (),
+    [F] TakeWhile<F> => &'a str,
+    ['b] Str<'b> => &'a str,
+    ['b] StrNoCase<'b> => &'a str,
+    ['b] Identifier<'b> => &'a str,
+    ['b] Keyword<'b> => &'a str,
+    [T] CheckedInteger<T> => T,
+    [T] Float<T> => T,
+    [] StringLiteral => Cow<'a, str>,
+    [T, F] Function<F> => T,
+    ['b] LineComment<'b> => &'a str,
+    ['b] BlockComment<'b> => &'a str,
+    ['b] Trivia<'b> => (),
+    // 这两个是按顺序一个个试的，本来就只给一种结果
+    ['p, T] Pratt<'p, T> => T,
+    [T, P] Permutation<P> => T,
+    [S] GetState<S> => S,
+    [S] SetState<S> => (),
+    [S, F] UpdateState<S, F> => (),
+}
+
+// 普通的函数也只有一种结果，比如integer、digit
+impl<'a, T, F> AllParser<'a, T> for F
+where
+    F: Fn(&'a str) -> Option<(T, &'a str)>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        self(input).into_iter().collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct AllFunction<F>(F);
+
+impl<'a, T, F> AllParser<'a, T> for AllFunction<F>
+where
+    F: Fn(&'a str) -> Vec<(T, &'a str)>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        (self.0)(input)
+    }
+}
+
+// 当成普通的parser用的时候取第一种结果
+impl<'a, T, F> Parser<'a, T> for AllFunction<F>
+where
+    F: Fn(&'a str) -> Vec<(T, &'a str)>,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        (self.0)(input).into_iter().next()
+    }
+}
+
+/// a parser from a function returning every way it matches, for recursive ambiguous rules
+pub fn all_function<T, F>(f: F) -> AllFunction<F>
+where
+    F: Fn(&str) -> Vec<(T, &str)>,
+{
+    AllFunction(f)
+}
+
+impl<'a, T, P1, P2> AllParser<'a, T> for Choice<P1, P2>
+where
+    P1: AllParser<'a, T>,
+    P2: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        // 两边都要，不再是第一个成功就结束
+        let mut res = self.0.parse_all(input);
+        res.extend(self.1.parse_all(input));
+        res
+    }
+}
+
+impl<'a, T1, P1, T2, F> AllParser<'a, T2> for Map<T1, P1, F>
+where
+    P1: AllParser<'a, T1>,
+    F: Fn(T1) -> T2,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T2, &'a str)> {
+        self.0
+            .parse_all(input)
+            .into_iter()
+            .map(|(a, remaining)| ((self.1)(a), remaining))
+            .collect()
+    }
+}
+
+impl<'a, T1, P1, T2, P2, F> AllParser<'a, T2> for AndThen<T1, P1, F>
+where
+    P1: AllParser<'a, T1>,
+    P2: AllParser<'a, T2>,
+    F: Fn(T1) -> P2,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T2, &'a str)> {
+        self.0
+            .parse_all(input)
+            .into_iter()
+            .flat_map(|(a, remaining)| (self.1)(a).parse_all(remaining))
+            .collect()
+    }
+}
+
+impl<'a, T1, P1, T2, P2> AllParser<'a, T1> for Left<T1, P1, T2, P2>
+where
+    P1: AllParser<'a, T1>,
+    P2: AllParser<'a, T2>,
+    T1: Clone,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T1, &'a str)> {
+        let mut res = vec![];
+        for (a, tail) in self.0.parse_all(input) {
+            for (_, remaining) in self.1.parse_all(tail) {
+                res.push((a.clone(), remaining));
+            }
+        }
+        res
+    }
+}
+
+impl<'a, T1, P1, T2, P2> AllParser<'a, T2> for Right<T1, P1, T2, P2>
+where
+    P1: AllParser<'a, T1>,
+    P2: AllParser<'a, T2>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T2, &'a str)> {
+        self.0
+            .parse_all(input)
+            .into_iter()
+            .flat_map(|(_, tail)| self.1.parse_all(tail))
+            .collect()
+    }
+}
+
+// 重复min到max次的所有可能，次数多的在前面，和many的贪心一致
+// 不吃输入的结果直接丢掉，不然会无限重复下去
+fn repeat_all<'a, T, P>(p: &P, input: &'a str, min: usize, max: usize) -> Vec<(Vec<T>, &'a str)>
+where
+    P: AllParser<'a, T>,
+    T: Clone,
+{
+    let mut res = vec![];
+    let mut frontier = vec![(vec![], input)];
+    let mut n = 0;
+
+    while !frontier.is_empty() {
+        let mut next = vec![];
+        for (items, input) in frontier {
+            if n < max {
+                for (a, remaining) in p.parse_all(input) {
+                    if remaining.len() < input.len() {
+                        let mut items = items.clone();
+                        items.push(a);
+                        next.push((items, remaining));
+                    }
+                }
+            }
+            if n >= min {
+                res.push((items, input));
+            }
+        }
+        frontier = next;
+        n += 1;
+    }
+
+    res.reverse();
+    res
+}
+
+impl<'a, T, P> AllParser<'a, Vec<T>> for Many<P>
+where
+    P: AllParser<'a, T>,
+    T: Clone,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(Vec<T>, &'a str)> {
+        repeat_all(&self.0, input, 0, usize::MAX)
+    }
+}
+
+impl<'a, T, P> AllParser<'a, Vec<T>> for Many1<P>
+where
+    P: AllParser<'a, T>,
+    T: Clone,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(Vec<T>, &'a str)> {
+        repeat_all(&self.0, input, 1, usize::MAX)
+    }
+}
+
+impl<'a, T, P> AllParser<'a, Vec<T>> for Count<P>
+where
+    P: AllParser<'a, T>,
+    T: Clone,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(Vec<T>, &'a str)> {
+        repeat_all(&self.0, input, self.1, self.1)
+    }
+}
+
+impl<'a, T, P> AllParser<'a, Vec<T>> for Repeat<P>
+where
+    P: AllParser<'a, T>,
+    T: Clone,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(Vec<T>, &'a str)> {
+        repeat_all(&self.0, input, self.1, self.2)
+    }
+}
+
+// 和Parser一样，Parser<char>重复出来的也可以是String
+fn collect_strings(res: Vec<(Vec<char>, &str)>) -> Vec<(String, &str)> {
+    res.into_iter()
+        .map(|(v, remaining)| (v.into_iter().collect(), remaining))
+        .collect()
+}
+
+impl<'a, P> AllParser<'a, String> for Many<P>
+where
+    P: AllParser<'a, char>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(String, &'a str)> {
+        collect_strings(repeat_all(&self.0, input, 0, usize::MAX))
+    }
+}
+
+impl<'a, P> AllParser<'a, String> for Many1<P>
+where
+    P: AllParser<'a, char>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(String, &'a str)> {
+        collect_strings(repeat_all(&self.0, input, 1, usize::MAX))
+    }
+}
+
+impl<'a, P> AllParser<'a, String> for Count<P>
+where
+    P: AllParser<'a, char>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(String, &'a str)> {
+        collect_strings(repeat_all(&self.0, input, self.1, self.1))
+    }
+}
+
+impl<'a, P> AllParser<'a, String> for Repeat<P>
+where
+    P: AllParser<'a, char>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(String, &'a str)> {
+        collect_strings(repeat_all(&self.0, input, self.1, self.2))
+    }
+}
+
+// end每次都先试，成功的每一种都是一个结果，然后p再往前走一步。少的在前面，和many_till一致
+fn many_till_all<'a, T1, P1, T2, P2>(p: &P1, end: &P2, input: &'a str) -> Vec<(Vec<T1>, &'a str)>
+where
+    P1: AllParser<'a, T1>,
+    P2: AllParser<'a, T2>,
+    T1: Clone,
+{
+    let mut res = vec![];
+    let mut frontier = vec![(vec![], input)];
+
+    while !frontier.is_empty() {
+        let mut next = vec![];
+        for (items, input) in frontier {
+            for (_, remaining) in end.parse_all(input) {
+                res.push((items.clone(), remaining));
+            }
+            for (a, remaining) in p.parse_all(input) {
+                if remaining.len() < input.len() {
+                    let mut items = items.clone();
+                    items.push(a);
+                    next.push((items, remaining));
+                }
+            }
+        }
+        frontier = next;
+    }
+
+    res
+}
+
+impl<'a, T1, P1, T2, P2> AllParser<'a, Vec<T1>> for ManyTill<T1, P1, T2, P2>
+where
+    P1: AllParser<'a, T1>,
+    P2: AllParser<'a, T2>,
+    T1: Clone,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(Vec<T1>, &'a str)> {
+        many_till_all(&self.0, &self.1, input)
+    }
+}
+
+impl<'a, P1, T2, P2> AllParser<'a, String> for ManyTill<char, P1, T2, P2>
+where
+    P1: AllParser<'a, char>,
+    P2: AllParser<'a, T2>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(String, &'a str)> {
+        collect_strings(many_till_all(&self.0, &self.1, input))
+    }
+}
+
+// 第一个能让p成功的位置，和p在那里的每一种结果
+fn find_all<'a, T, P>(p: &P, input: &'a str) -> Option<(usize, Vec<(T, &'a str)>)>
+where
+    P: AllParser<'a, T>,
+{
+    input
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(input.len()))
+        .map(|i| (i, p.parse_all(&input[i..])))
+        .find(|(_, res)| !res.is_empty())
+}
+
+impl<'a, T, P> AllParser<'a, ()> for SkipUntil<T, P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<((), &'a str)> {
+        match find_all(&self.0, input) {
+            Some((_, res)) => res
+                .into_iter()
+                .map(|(_, remaining)| ((), remaining))
+                .collect(),
+            None => vec![],
+        }
+    }
+}
+
+impl<'a, T, P> AllParser<'a, &'a str> for TakeUntil<T, P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(&'a str, &'a str)> {
+        // pattern不吃掉，怎么匹配的都一样
+        match find_all(&self.0, input) {
+            Some((i, _)) => vec![input.split_at(i)],
+            None => vec![],
+        }
+    }
+}
+
+impl<'a, T, P> AllParser<'a, Option<T>> for Optional<T, P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(Option<T>, &'a str)> {
+        let mut res: Vec<_> = self
+            .0
+            .parse_all(input)
+            .into_iter()
+            .map(|(a, remaining)| (Some(a), remaining))
+            .collect();
+        res.push((None, input));
+        res
+    }
+}
+
+impl<'a, T, P> AllParser<'a, ()> for SkipOptional<T, P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<((), &'a str)> {
+        let mut res: Vec<_> = self
+            .0
+            .parse_all(input)
+            .into_iter()
+            .map(|(_, remaining)| ((), remaining))
+            .collect();
+        res.push(((), input));
+        res
+    }
+}
+
+// 至少一个元素的所有分法，多的在前面
+fn separated_all<'a, T1, P1, T2, P2>(
+    p: &P1,
+    separator: &P2,
+    input: &'a str,
+) -> Vec<(Vec<T1>, &'a str)>
+where
+    P1: AllParser<'a, T1>,
+    P2: AllParser<'a, T2>,
+    T1: Clone,
+{
+    let mut res = vec![];
+    let mut frontier: Vec<_> = p
+        .parse_all(input)
+        .into_iter()
+        .map(|(a, remaining)| (vec![a], remaining))
+        .collect();
+
+    while !frontier.is_empty() {
+        let mut next = vec![];
+        for (items, input) in frontier {
+            for (_, tail) in separator.parse_all(input) {
+                for (a, remaining) in p.parse_all(tail) {
+                    if remaining.len() < input.len() {
+                        let mut items = items.clone();
+                        items.push(a);
+                        next.push((items, remaining));
+                    }
+                }
+            }
+            res.push((items, input));
+        }
+        frontier = next;
+    }
+
+    res.reverse();
+    res
+}
+
+impl<'a, T, P> AllParser<'a, T> for OrDefault<T, P>
+where
+    P: AllParser<'a, T>,
+    T: Default,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        let mut res = self.0.parse_all(input);
+        res.push((T::default(), input));
+        res
+    }
+}
+
+impl<'a, T, P> AllParser<'a, T> for Or<T, P>
+where
+    P: AllParser<'a, T>,
+    T: Clone,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        let mut res = self.0.parse_all(input);
+        res.push((self.1.clone(), input));
+        res
+    }
+}
+
+impl<'a, T1, P1, T2, P2> AllParser<'a, Vec<T1>> for SeparatedBy<T1, P1, T2, P2>
+where
+    P1: AllParser<'a, T1>,
+    P2: AllParser<'a, T2>,
+    T1: Clone,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(Vec<T1>, &'a str)> {
+        let mut res = separated_all(&self.0, &self.1, input);
+        res.push((vec![], input));
+        res
+    }
+}
+
+impl<'a, T1, P1, T2, P2> AllParser<'a, Vec<T1>> for SeparatedEndBy<T1, P1, T2, P2>
+where
+    P1: AllParser<'a, T1>,
+    P2: AllParser<'a, T2>,
+    T1: Clone,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(Vec<T1>, &'a str)> {
+        // 每一种分法后面都可以再吃一个分隔符
+        let mut res = vec![];
+        for (items, remaining) in separated_all(&self.0, &self.1, input) {
+            for (_, tail) in self.1.parse_all(remaining) {
+                res.push((items.clone(), tail));
+            }
+            res.push((items, remaining));
+        }
+        res.push((vec![], input));
+        res
+    }
+}
+
+impl<'a, T1, P1, T2, P2> AllParser<'a, T1> for ChainLeft1<T1, P1, T2, P2>
+where
+    P1: AllParser<'a, T1>,
+    P2: AllParser<'a, T2>,
+    T1: Clone,
+    T2: Fn(T1, T1) -> T1,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T1, &'a str)> {
+        // 一边走一边从左往右合并
+        let mut res = vec![];
+        let mut frontier = self.0.parse_all(input);
+
+        while !frontier.is_empty() {
+            let mut next = vec![];
+            for (acc, input) in frontier {
+                for (f, tail) in self.1.parse_all(input) {
+                    for (w, remaining) in self.0.parse_all(tail) {
+                        if remaining.len() < input.len() {
+                            next.push((f(acc.clone(), w), remaining));
+                        }
+                    }
+                }
+                res.push((acc, input));
+            }
+            frontier = next;
+        }
+
+        res.reverse();
+        res
+    }
+}
+
+impl<'a, T1, P1, T2, P2> AllParser<'a, T1> for ChainRight1<T1, P1, T2, P2>
+where
+    P1: AllParser<'a, T1>,
+    P2: AllParser<'a, T2>,
+    T1: Clone,
+    T2: Fn(T1, T1) -> T1 + Clone,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T1, &'a str)> {
+        // 从右往左合并要等到最后，所以先把操作数和运算符都存下来
+        let mut res = vec![];
+        let mut frontier: Vec<_> = self
+            .0
+            .parse_all(input)
+            .into_iter()
+            .map(|(v, remaining)| (vec![v], vec![], remaining))
+            .collect();
+
+        while !frontier.is_empty() {
+            let mut next = vec![];
+            for (operands, operators, input) in frontier {
+                for (f, tail) in self.1.parse_all(input) {
+                    for (w, remaining) in self.0.parse_all(tail) {
+                        if remaining.len() < input.len() {
+                            let (mut operands, mut operators) =
+                                (operands.clone(), operators.clone());
+                            operands.push(w);
+                            operators.push(f.clone());
+                            next.push((operands, operators, remaining));
+                        }
+                    }
+                }
+                res.push((fold_right(operands, operators), input));
+            }
+            frontier = next;
+        }
+
+        res.reverse();
+        res
+    }
+}
+
+fn fold_right<T1, T2>(mut operands: Vec<T1>, mut operators: Vec<T2>) -> T1
+where
+    T2: Fn(T1, T1) -> T1,
+{
+    let mut acc = operands.pop().unwrap();
+    while let (Some(f), Some(v)) = (operators.pop(), operands.pop()) {
+        acc = f(v, acc);
+    }
+    acc
+}
+
+impl<'a, T, P> AllParser<'a, &'a str> for Recognize<T, P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(&'a str, &'a str)> {
+        self.0
+            .parse_all(input)
+            .into_iter()
+            .map(|(_, remaining)| (&input[..input.len() - remaining.len()], remaining))
+            .collect()
+    }
+}
+
+impl<'a, T, P> AllParser<'a, T> for LookAhead<P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        self.0
+            .parse_all(input)
+            .into_iter()
+            .map(|(a, _)| (a, input))
+            .collect()
+    }
+}
+
+impl<'a, T, P> AllParser<'a, ()> for Not<T, P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<((), &'a str)> {
+        if self.0.parse_all(input).is_empty() {
+            vec![((), input)]
+        } else {
+            vec![]
+        }
+    }
+}
+
+impl<'a, T, P> AllParser<'a, T> for MaxDepth<P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        let depth = DEPTH.with(|v| v.get());
+        if depth >= self.1 {
+            return vec![];
+        }
+
+        DEPTH.with(|v| v.set(depth + 1));
+        let _guard = DepthGuard(depth);
+        self.0.parse_all(input)
+    }
+}
+
+impl<'a, T, P> AllParser<'a, (T, Span)> for Spanned<P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<((T, Span), &'a str)> {
+        anchored(input, || {
+            let start = offset_of(input);
+            self.0
+                .parse_all(input)
+                .into_iter()
+                .map(|(a, remaining)| {
+                    let end = offset_of(remaining);
+                    ((a, Span { start, end }), remaining)
+                })
+                .collect()
+        })
+    }
+}
+
+impl<'a, T, P> AllParser<'a, Located<T>> for Locate<P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(Located<T>, &'a str)> {
+        anchored_text(input, || {
+            let start = position_of(input);
+            self.0
+                .parse_all(input)
+                .into_iter()
+                .map(|(value, remaining)| {
+                    let end = position_of(remaining);
+                    (Located { value, start, end }, remaining)
+                })
+                .collect()
+        })
+    }
+}
+
+impl<'a, T, P> AllParser<'a, T> for Origin<P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        with_origin(input, || self.0.parse_all(input))
+    }
+}
+
+// 下面这些在parse_all里只是原样传下去：补全、语法树和trace都只跟着普通的parse走
+impl<'a, 'b, T, P> AllParser<'a, T> for Label<'b, P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        self.0.parse_all(input)
+    }
+}
+
+impl<'a, T, P> AllParser<'a, T> for Node<P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        self.0.parse_all(input)
+    }
+}
+
+impl<'a, T, P> AllParser<'a, T> for Token<P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        self.0.parse_all(input)
+    }
+}
+
+impl<'a, 'b, T, P> AllParser<'a, T> for Trace<'b, P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        self.0.parse_all(input)
+    }
+}
+
+impl<'a, T, P> AllParser<'a, T> for WithPos<P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        with_reference(input, || self.0.parse_all(input))
+    }
+}
+
+// block里的每一项都要和第一项对齐
+struct Aligned<'r, P>(&'r P);
+
+impl<'a, T, P> AllParser<'a, T> for Aligned<'_, P>
+where
+    P: AllParser<'a, T>,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(T, &'a str)> {
+        match check_indent(input) {
+            Some(_) => self.0.parse_all(input),
+            None => vec![],
+        }
+    }
+}
+
+impl<'a, T, P> AllParser<'a, Vec<T>> for Block<P>
+where
+    P: AllParser<'a, T>,
+    T: Clone,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<(Vec<T>, &'a str)> {
+        with_reference(input, || {
+            repeat_all(&Aligned(&self.0), input, 1, usize::MAX)
+        })
+    }
+}
+
+impl<'a, T1, P1, T2, P2> AllParser<'a, (T1, Vec<T2>)> for IndentedBlock<T1, P1, P2>
+where
+    P1: AllParser<'a, T1>,
+    P2: AllParser<'a, T2>,
+    T1: Clone,
+    T2: Clone,
+{
+    fn parse_all(&self, input: &'a str) -> Vec<((T1, Vec<T2>), &'a str)> {
+        with_reference(input, || {
+            let mut res = vec![];
+            for (header, tail) in self.0.parse_all(input) {
+                // 没有缩进的内容，或者只用了一部分，都是一种解析
+                if indented(tail).is_some() {
+                    let items =
+                        with_reference(tail, || repeat_all(&Aligned(&self.1), tail, 1, usize::MAX));
+                    for (items, remaining) in items {
+                        res.push(((header.clone(), items), remaining));
+                    }
+                }
+                res.push(((header, vec![]), tail));
+            }
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        any, block, char, eof, epsilon, indented_block, integer, origin, skip_until, string,
+        symbol, take_until,
+    };
+
+    #[test]
+    fn all_parses_segmentation() {
+        let word = string("abc")
+            .choice(string("ab"))
+            .choice(string("a"))
+            .choice(string("bc"))
+            .choice(string("c"));
+        let parser = word.clone().many();
+        assert_eq!(
+            parser.parse_complete("abc"),
+            vec![vec!["a", "bc"], vec!["ab", "c"], vec!["abc"]]
+        );
+        assert_eq!(parser.parse_all("abc").len(), 6); // 还有[]、[a]、[ab]这种没吃完的
+
+        // 同样的定义当普通的parser用，还是只取第一种
+        assert_eq!(
+            dbg!(Parser::<Vec<&str>>::parse(&word.many(), "abc")),
+            Some((vec!["abc"], ""))
+        );
+    }
+
+    #[test]
+    fn all_parses_optional_and_separated() {
+        let parser = char('a')
+            .optional()
+            .left(char('a').many1().map(|v: Vec<char>| v));
+        assert_eq!(
+            parser.parse_all("aa"),
+            vec![(Some('a'), ""), (None, ""), (None, "a")]
+        );
+
+        let parser = integer.map(|v: i64| v).separated_by(symbol(",")).left(eof);
+        assert_eq!(parser.parse_complete("1, 2,3"), vec![vec![1, 2, 3]]);
+        assert!(char('x').parse_all("y").is_empty());
+    }
+
+    #[test]
+    fn all_parses_separated_end_by() {
+        let word = string("a").choice(string("aa"));
+        let parser = word.separated_end_by(char(','));
+        assert_eq!(parser.parse_complete("aa,a,"), vec![vec!["aa", "a"]]);
+        assert_eq!(
+            parser.parse_all("a,"),
+            vec![(vec!["a"], ""), (vec!["a"], ","), (vec![], "a,")]
+        );
+    }
+
+    #[test]
+    fn all_parses_repetition() {
+        let parser = char('a')
+            .repeat(1..=2)
+            .left(char('a').many().map(|v: String| v));
+        let res: Vec<(String, &str)> = parser.parse_all("aa");
+        assert_eq!(
+            res,
+            vec![
+                ("aa".to_owned(), ""),
+                ("a".to_owned(), ""),
+                ("a".to_owned(), "a")
+            ]
+        );
+
+        let parser = any.many_till(string("b").choice(string("bb")));
+        assert_eq!(
+            parser.parse_all("abbb"),
+            vec![
+                ("a".to_owned(), "bb"),
+                ("a".to_owned(), "b"),
+                ("ab".to_owned(), "b"),
+                ("ab".to_owned(), ""),
+                ("abb".to_owned(), ""),
+            ]
+        );
+
+        let parser = char('a').many1().map(|v: String| v);
+        assert_eq!(
+            parser.parse_all("aab"),
+            vec![("aa".to_owned(), "b"), ("a".to_owned(), "ab")]
+        );
+    }
+
+    #[test]
+    fn all_parses_wrappers() {
+        let ab = string("ab").choice(string("a"));
+        assert_eq!(
+            ab.clone().or("x").parse_all("ab"),
+            vec![("ab", ""), ("a", "b"), ("x", "ab")]
+        );
+        assert_eq!(ab.clone().or_default().parse_all("c"), vec![("", "c")]);
+        assert_eq!(take_until(ab.clone()).parse_all("xxab"), vec![("xx", "ab")]);
+        assert_eq!(
+            skip_until(ab.clone()).parse_all("xxab"),
+            vec![((), ""), ((), "b")]
+        );
+
+        let parser = origin(char(' ').right(ab.spanned().label("ab").max_depth(1)));
+        assert_eq!(
+            parser.parse_all(" ab"),
+            vec![
+                (("ab", Span { start: 1, end: 3 }), ""),
+                (("a", Span { start: 1, end: 2 }), "b")
+            ]
+        );
+    }
+
+    #[test]
+    fn all_parses_ambiguous_expression() {
+        // E := n | E - E，两种结合方式都要：从左往右合并的和从右往左合并的
+        let number = integer.map(|v: i64| v).lexeme();
+        let minus = symbol("-").map(|_| (|a, b| a - b) as fn(i64, i64) -> i64);
+        let parser = number
+            .clone()
+            .chain_left1(minus.clone())
+            .choice(number.chain_right1(minus));
+        assert_eq!(parser.parse_complete("8 - 4 - 2"), vec![2, 6]);
+        // 没吃完的前缀也在里面，长的在前面
+        assert_eq!(
+            parser.parse_all("8 - 4 - 2")[..3],
+            [(2, ""), (4, "- 2"), (8, "- 4 - 2")]
+        );
+
+        let parser = char('a').right(all_function(|input| {
+            char('b').choice(epsilon.map(|_| 'e')).parse_all(input)
+        }));
+        assert_eq!(dbg!(parser.parse("ab")), Some(('b', "")));
+    }
+
+    #[test]
+    fn all_parses_block() {
+        // 每一项都能分成[a, b]或者[ab]
+        let word = string("a").choice(string("b")).choice(string("ab"));
+        let item = word.many1().left(char(';')).lexeme().trace("item");
+        let parser = origin(block(item.clone()));
+        assert_eq!(parser.parse_complete("ab;\nab;").len(), 4);
+        // 第二行没对齐，块在那里停下
+        let res = parser.parse_all("ab;\n ab;");
+        assert_eq!(res.len(), 2);
+        assert!(res.iter().all(|(_, remaining)| *remaining == "ab;"));
+
+        let parser = origin(indented_block(string("x").lexeme(), item));
+        let res = parser.parse_complete("x\n ab;\n ab;");
+        assert_eq!(res.len(), 4);
+        assert!(res.contains(&("x", vec![vec!["a", "b"], vec!["ab"]])));
+        assert_eq!(parser.parse_all("x\nab;"), vec![(("x", vec![]), "ab;")]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+
+/// parse_with_budget gave up because the parser took more steps than allowed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BudgetExhausted;
+
+impl fmt::Display for BudgetExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "parse budget exhausted")
+    }
+}
+
+impl Error for BudgetExhausted {}
+
+struct Budget {
+    left: usize,
+    exhausted: bool,
+}
+
+thread_local! {
+    // 可以嵌套，里面的预算用掉的步数外面的也要算
+    static BUDGETS: RefCell<Vec<Budget>> = const { RefCell::new(Vec::new()) };
+}
+
+// parser panic了也要把这一层弹出去，不然这个线程以后的parse都会被它限制
+struct BudgetGuard;
+
+impl Drop for BudgetGuard {
+    fn drop(&mut self) {
+        BUDGETS.with(|v| v.borrow_mut().pop());
+    }
+}
+
+pub(crate) fn with_budget<R, F>(steps: usize, f: F) -> Result<R, BudgetExhausted>
+where
+    F: FnOnce() -> R,
+{
+    BUDGETS.with(|v| {
+        v.borrow_mut().push(Budget {
+            left: steps,
+            exhausted: false,
+        })
+    });
+    let guard = BudgetGuard;
+    let res = f();
+    let exhausted = BUDGETS.with(|v| v.borrow().last().unwrap().exhausted);
+    drop(guard);
+    if exhausted {
+        Err(BudgetExhausted)
+    } else {
+        Ok(res)
+    }
+}
+
+// 用完了就返回false。用完以后所有的基本parser都立刻失败，choice再怎么回溯也很快就结束了
+fn step() -> bool {
+    BUDGETS.with(|v| {
+        let mut budgets = v.borrow_mut();
+        let mut ok = true;
+        for budget in budgets.iter_mut() {
+            if budget.left == 0 {
+                budget.exhausted = true;
+                ok = false;
+            }
+        }
+        if ok {
+            budgets.iter_mut().for_each(|v| v.left -= 1);
+        }
+        ok
+    })
+}
+
+/// every primitive parser runs its body through this, so it counts as a step of parse_with_budget
+pub(crate) fn primitive<'a, T, F>(f: F) -> Option<(T, &'a str)>
+where
+    F: FnOnce() -> Option<(T, &'a str)>,
+{
+    if !step() {
+        return None;
+    }
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{char, epsilon, integer, not, symbol, Parser};
+    use std::panic::catch_unwind;
+
+    // 每一层都把两个分支试一遍，输入不匹配的时候是指数级的
+    fn s(input: &str) -> Option<((), &str)> {
+        char('a')
+            .right(s)
+            .left(char('b'))
+            .choice(char('a').right(s).left(char('c')))
+            .choice(epsilon)
+            .parse(input)
+    }
+
+    #[test]
+    fn budget_exhausted() {
+        assert_eq!(dbg!(s.parse_with_budget(100, "aabb")), Ok(Some(((), ""))));
+        assert_eq!(dbg!(s.parse_with_budget(100, "x")), Ok(Some(((), "x"))));
+
+        let input = "a".repeat(40) + "d";
+        assert_eq!(
+            dbg!(s.parse_with_budget(10_000, &input)),
+            Err(BudgetExhausted)
+        );
+        assert_eq!(BudgetExhausted.to_string(), "parse budget exhausted");
+    }
+
+    #[test]
+    fn budget_exhausted_inside_lookahead() {
+        // not和look_ahead把失败吞掉了，预算用完还是要报出来
+        let parser = not(s.right(char('d')));
+        let input = "a".repeat(40) + "d";
+        assert_eq!(
+            dbg!(parser.parse_with_budget(10_000, &input)),
+            Err(BudgetExhausted)
+        );
+        let parser = s.right(char('d')).look_ahead().or('x');
+        assert_eq!(
+            dbg!(parser.parse_with_budget(10_000, &input)),
+            Err(BudgetExhausted)
+        );
+        assert_eq!(
+            dbg!(not(char('x')).parse_with_budget(10, "a")),
+            Ok(Some(((), "a")))
+        );
+    }
+
+    #[test]
+    fn budget_popped_after_panic() {
+        let res = catch_unwind(|| {
+            char('a')
+                .map(|_| -> char { panic!("boom") })
+                .parse_with_budget(1, "a")
+        });
+        assert!(res.is_err());
+        // 预算没有留下来，这里可以随便走
+        assert_eq!(dbg!(s.parse_with_budget(100, "aabb")), Ok(Some(((), ""))));
+        assert!(BUDGETS.with(|v| v.borrow().is_empty()));
+    }
+
+    #[test]
+    fn budget_nested() {
+        // 里面的预算够用，外面的不够
+        let inner = |input| {
+            integer
+                .map(|v: i64| v)
+                .parse_with_budget(1000, input)
+                .ok()
+                .flatten()
+        };
+        let parser = inner.separated_by(symbol(","));
+        assert_eq!(
+            dbg!(parser.parse_with_budget(1000, "1, 2, 3")),
+            Ok(Some((vec![1, 2, 3], "")))
+        );
+        assert_eq!(
+            dbg!(parser.parse_with_budget(5, "1, 2, 3")),
+            Err(BudgetExhausted)
+        );
+    }
+}
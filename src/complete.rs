@@ -0,0 +1,157 @@
+use crate::Parser;
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+#[derive(Default)]
+struct Collector {
+    expected: Vec<String>,
+    quiet: usize, // label里面的东西不单独记
+}
+
+thread_local! {
+    static COLLECTORS: RefCell<Vec<Collector>> = const { RefCell::new(Vec::new()) };
+}
+
+/// a primitive was tried at the end of input and wanted this, nothing happens outside completions
+pub(crate) fn expect<F>(f: F)
+where
+    F: FnOnce() -> String,
+{
+    COLLECTORS.with(|v| {
+        if let Some(collector) = v.borrow_mut().last_mut() {
+            if collector.quiet > 0 {
+                return;
+            }
+            let item = f();
+            if !collector.expected.contains(&item) {
+                collector.expected.push(item);
+            }
+        }
+    });
+}
+
+struct CollectorGuard;
+
+impl Drop for CollectorGuard {
+    fn drop(&mut self) {
+        COLLECTORS.with(|v| v.borrow_mut().pop());
+    }
+}
+
+// label里面不单独记，panic的时候也要退回来
+struct Quiet;
+
+impl Quiet {
+    fn new() -> Self {
+        COLLECTORS.with(|v| v.borrow_mut().last_mut().map(|v| v.quiet += 1));
+        Quiet
+    }
+}
+
+impl Drop for Quiet {
+    fn drop(&mut self) {
+        COLLECTORS.with(|v| v.borrow_mut().last_mut().map(|v| v.quiet -= 1));
+    }
+}
+
+#[derive(Clone)]
+pub struct Label<'b, P>(pub(crate) P, pub(crate) Cow<'b, str>);
+
+impl<P> Label<'_, P> {
+    // pratt的运算符也走这里，f就是self.0.parse或者self.0.skip
+    pub(crate) fn run<R, F>(&self, input: &str, f: F) -> Option<R>
+    where
+        F: FnOnce() -> Option<R>,
+    {
+        if !input.is_empty() {
+            return f();
+        }
+
+        // 在输入的结尾试了p，补全的时候提示名字，而不是p里面的一个个字符
+        let quiet = Quiet::new();
+        let res = f();
+        drop(quiet);
+        if res.is_none() {
+            expect(|| self.1.to_string());
+        }
+        res
+    }
+}
+
+impl<'a, 'b, T, P> Parser<'a, T> for Label<'b, P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        self.run(input, || self.0.parse(input))
+    }
+}
+
+/// what could come after input, in the order they are tried
+///
+/// char, string, symbol, string_no_case and keyword tried at the end of input contribute what they match, also when
+/// input ends in the middle of them (so "wh" gives "while"). p.label(name) contributes name instead.
+pub fn completions<'a, T, P>(p: &P, input: &'a str) -> Vec<String>
+where
+    P: Parser<'a, T>,
+{
+    COLLECTORS.with(|v| v.borrow_mut().push(Collector::default()));
+    let _guard = CollectorGuard;
+    p.parse(input);
+    COLLECTORS.with(|v| std::mem::take(&mut v.borrow_mut().last_mut().unwrap().expected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{char, epsilon, integer, keyword, string_no_case, symbol, whitespaces};
+    use std::panic::catch_unwind;
+
+    fn expr(input: &str) -> Option<(i64, &str)> {
+        let atom = integer
+            .map(|v: i64| v)
+            .label("number")
+            .lexeme()
+            .choice(expr.between(symbol("("), symbol(")")));
+        let operator = symbol("+")
+            .map(|_| (|a, b| a + b) as fn(i64, i64) -> i64)
+            .choice(symbol("-").map(|_| (|a, b| a - b) as fn(i64, i64) -> i64));
+        atom.chain_left1(operator).parse(input)
+    }
+
+    #[test]
+    fn completions_expression() {
+        let parser = whitespaces.right(expr);
+        assert_eq!(completions(&parser, "(1"), vec!["+", "-", ")"]);
+        assert_eq!(completions(&parser, "(1 + "), vec!["number", "("]);
+        assert_eq!(completions(&parser, ""), vec!["number", "("]);
+        assert_eq!(completions(&parser, "(1 + 2) "), vec!["+", "-"]);
+        assert!(completions(&parser, "1 x").is_empty());
+    }
+
+    #[test]
+    fn completions_partial_token() {
+        let statement = keyword("while")
+            .choice(keyword("where"))
+            .choice(keyword("if"))
+            .choice(string_no_case("SELECT"));
+        assert_eq!(completions(&statement, "wh"), vec!["while", "where"]);
+        assert_eq!(completions(&statement, "se"), vec!["SELECT"]);
+        assert!(completions(&statement, "x").is_empty());
+        assert_eq!(completions(&char('a').right(char('b')), "a"), vec!["b"]);
+    }
+
+    #[test]
+    fn completions_popped_after_panic() {
+        let res = catch_unwind(|| {
+            let parser = epsilon
+                .map(|_| -> char { panic!("boom") })
+                .label("a")
+                .choice(char('b'));
+            completions(&parser.label("outer"), "")
+        });
+        assert!(res.is_err());
+        assert!(COLLECTORS.with(|v| v.borrow().is_empty()));
+        assert_eq!(completions(&char('a').label("a"), ""), vec!["a"]);
+    }
+}
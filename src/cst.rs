@@ -0,0 +1,657 @@
+use crate::{Parser, Span};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// what a node or token is, like "expr" for a rule or "(" for a token
+pub type Kind = Cow<'static, str>;
+
+/// kind of whitespaces skipped by Whitespaces, lexeme and Trivia
+pub const WHITESPACE: &str = "whitespace";
+/// kind of comments skipped by Trivia
+pub const COMMENT: &str = "comment";
+/// kind of a character matched by satisfy
+pub const SATISFY: &str = "satisfy";
+/// kind of what take_while and friends matched
+pub const TAKE_WHILE: &str = "take_while";
+/// kind of a character matched by digit
+pub const DIGIT: &str = "digit";
+/// kind of integer and the checked integer parsers
+pub const INTEGER: &str = "integer";
+/// kind of float
+pub const FLOAT: &str = "float";
+/// kind of string_literal and raw_string_literal, quotes included
+pub const STRING: &str = "string";
+/// kind of input that was consumed but not recorded as any token
+pub const TEXT: &str = "text";
+
+/// a leaf of the green tree, immutable and shared
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GreenToken {
+    kind: Kind,
+    text: String,
+}
+
+impl GreenToken {
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenElement {
+    pub fn text_len(&self) -> usize {
+        match self {
+            GreenElement::Node(v) => v.text_len(),
+            GreenElement::Token(v) => v.text.len(),
+        }
+    }
+}
+
+/// an inner node of the green tree, knows its length but not where it is, so it can be shared
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GreenNode {
+    kind: Kind,
+    len: usize,
+    children: Vec<GreenElement>,
+}
+
+impl GreenNode {
+    pub fn new<K>(kind: K, children: Vec<GreenElement>) -> Self
+    where
+        K: Into<Kind>,
+    {
+        GreenNode {
+            kind: kind.into(),
+            len: children.iter().map(|v| v.text_len()).sum(),
+            children,
+        }
+    }
+
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn text_len(&self) -> usize {
+        self.len
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+}
+
+// 打印出来就是原来的输入
+impl fmt::Display for GreenNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for child in self.children.iter() {
+            match child {
+                GreenElement::Node(v) => write!(f, "{}", v)?,
+                GreenElement::Token(v) => f.write_str(&v.text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// a node of the red tree, a green node plus where it is and its parent
+#[derive(Clone, Debug)]
+pub struct SyntaxNode {
+    green: Rc<GreenNode>,
+    offset: usize,
+    parent: Option<Rc<SyntaxNode>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    offset: usize,
+    parent: Rc<SyntaxNode>,
+}
+
+#[derive(Clone, Debug)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: Rc<GreenNode>) -> Self {
+        SyntaxNode {
+            green,
+            offset: 0,
+            parent: None,
+        }
+    }
+
+    pub fn kind(&self) -> &str {
+        &self.green.kind
+    }
+
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.green
+    }
+
+    /// byte offsets from the start of the root
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.offset,
+            end: self.offset + self.green.len,
+        }
+    }
+
+    pub fn parent(&self) -> Option<&SyntaxNode> {
+        self.parent.as_deref()
+    }
+
+    pub fn children(&self) -> Vec<SyntaxElement> {
+        let parent = Rc::new(self.clone());
+        let mut offset = self.offset;
+        let mut res = vec![];
+
+        for child in self.green.children.iter() {
+            res.push(match child {
+                GreenElement::Node(v) => SyntaxElement::Node(SyntaxNode {
+                    green: v.clone(),
+                    offset,
+                    parent: Some(parent.clone()),
+                }),
+                GreenElement::Token(v) => SyntaxElement::Token(SyntaxToken {
+                    green: v.clone(),
+                    offset,
+                    parent: parent.clone(),
+                }),
+            });
+            offset += child.text_len();
+        }
+
+        res
+    }
+
+    /// child nodes, without tokens
+    pub fn child_nodes(&self) -> Vec<SyntaxNode> {
+        self.children()
+            .into_iter()
+            .filter_map(|v| match v {
+                SyntaxElement::Node(v) => Some(v),
+                SyntaxElement::Token(_) => None,
+            })
+            .collect()
+    }
+
+    /// all tokens under this node in order, trivia included
+    pub fn tokens(&self) -> Vec<SyntaxToken> {
+        let mut res = vec![];
+        for child in self.children() {
+            match child {
+                SyntaxElement::Node(v) => res.extend(v.tokens()),
+                SyntaxElement::Token(v) => res.push(v),
+            }
+        }
+        res
+    }
+
+    pub fn text(&self) -> String {
+        self.green.to_string()
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.green)
+    }
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> &str {
+        &self.green.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.offset,
+            end: self.offset + self.green.text.len(),
+        }
+    }
+
+    pub fn parent(&self) -> &SyntaxNode {
+        &self.parent
+    }
+
+    /// whitespaces and comments
+    pub fn is_trivia(&self) -> bool {
+        self.kind() == WHITESPACE || self.kind() == COMMENT
+    }
+}
+
+enum Event {
+    Start(Kind, usize),
+    Token(Kind, usize, usize),
+    Finish(usize),
+}
+
+struct Builder {
+    len: usize, // 整个输入的长度，offset = len - 剩下的长度
+    events: Vec<Event>,
+    quiet: usize, // token(kind)里面的东西不单独记
+}
+
+thread_local! {
+    static BUILDERS: RefCell<Vec<Builder>> = const { RefCell::new(Vec::new()) };
+}
+
+// 所有线程里正在进行的syntax_tree有几个。基本的parser每次都要问一下，没有的话连thread local都不用碰
+static BUILDING: AtomicUsize = AtomicUsize::new(0);
+
+// parser panic了也要把builder弹出去，里面是push之前有几个builder
+struct BuilderGuard(usize);
+
+impl Drop for BuilderGuard {
+    fn drop(&mut self) {
+        BUILDING.fetch_sub(1, Ordering::Relaxed);
+        BUILDERS.with(|v| v.borrow_mut().truncate(self.0));
+    }
+}
+
+impl Builder {
+    fn offset(&self, remaining: &str) -> usize {
+        self.len.saturating_sub(remaining.len())
+    }
+
+    // 失败的分支留下来的东西一定在当前位置后面（位置只会往前走），先把它们扔掉，再把中间没记下来的输入补成TEXT
+    fn advance(&mut self, offset: usize) {
+        let mut depth = 0;
+        while let Some(event) = self.events.last() {
+            match *event {
+                Event::Token(_, _, end) if depth > 0 || end > offset => {}
+                Event::Finish(end) if depth > 0 || end > offset => depth += 1,
+                Event::Start(..) if depth > 0 => depth -= 1,
+                _ => break,
+            }
+            self.events.pop();
+        }
+
+        let last = match self.events.last() {
+            Some(Event::Start(_, v)) | Some(Event::Token(_, _, v)) | Some(Event::Finish(v)) => *v,
+            None => 0,
+        };
+        if last < offset {
+            self.events.push(Event::Token(TEXT.into(), last, offset));
+        }
+    }
+
+    fn token(&mut self, kind: Kind, start: usize, end: usize) {
+        if self.quiet > 0 || start == end {
+            return;
+        }
+        self.advance(start);
+        self.events.push(Event::Token(kind, start, end));
+    }
+
+    fn build(mut self, kind: Kind, input: &str, end: usize) -> GreenNode {
+        self.advance(end);
+        let mut stack = vec![(kind, vec![])];
+
+        for event in self.events {
+            match event {
+                Event::Start(kind, _) => stack.push((kind, vec![])),
+                Event::Token(kind, start, end) => {
+                    let token = GreenToken {
+                        kind,
+                        text: input[start..end].to_owned(),
+                    };
+                    stack
+                        .last_mut()
+                        .unwrap()
+                        .1
+                        .push(GreenElement::Token(Rc::new(token)));
+                }
+                Event::Finish(_) => {
+                    let (kind, children) = stack.pop().unwrap();
+                    let node = GreenNode::new(kind, children);
+                    stack
+                        .last_mut()
+                        .unwrap()
+                        .1
+                        .push(GreenElement::Node(Rc::new(node)));
+                }
+            }
+        }
+
+        let (kind, children) = stack.pop().unwrap();
+        GreenNode::new(kind, children)
+    }
+}
+
+fn with_builder<R, F>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut Builder) -> R,
+{
+    if BUILDING.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    BUILDERS.with(|v| v.borrow_mut().last_mut().map(f))
+}
+
+/// record what a primitive consumed as a token, nothing happens outside syntax_tree
+pub(crate) fn token<F>(kind: F, input: &str, remaining: &str)
+where
+    F: FnOnce() -> Kind,
+{
+    with_builder(|builder| {
+        let (start, end) = (builder.offset(input), builder.offset(remaining));
+        if builder.quiet == 0 && start < end {
+            builder.token(kind(), start, end);
+        }
+    });
+}
+
+#[derive(Clone)]
+pub struct Node<P>(pub(crate) P, pub(crate) Kind);
+
+impl<'a, T, P> Parser<'a, T> for Node<P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        // token(kind)里面的节点也不记
+        let checkpoint = with_builder(|builder| {
+            if builder.quiet > 0 {
+                return None;
+            }
+            let start = builder.offset(input);
+            builder.advance(start);
+            builder.events.push(Event::Start(self.1.clone(), start));
+            Some(builder.events.len() - 1)
+        })
+        .flatten();
+
+        let res = self.0.parse(input);
+        if let Some(checkpoint) = checkpoint {
+            with_builder(|builder| match &res {
+                Some((_, remaining)) => {
+                    let end = builder.offset(remaining);
+                    builder.advance(end);
+                    builder.events.push(Event::Finish(end));
+                }
+                None => builder.events.truncate(checkpoint),
+            });
+        }
+        res
+    }
+}
+
+#[derive(Clone)]
+pub struct Token<P>(pub(crate) P, pub(crate) Kind);
+
+impl<P> Token<P> {
+    // pratt的运算符也走这里，f就是self.0.parse或者self.0.skip
+    pub(crate) fn run<'a, R, F>(&self, input: &'a str, f: F) -> Option<(R, &'a str)>
+    where
+        F: FnOnce() -> Option<(R, &'a str)>,
+    {
+        with_builder(|builder| builder.quiet += 1);
+        let res = f();
+        with_builder(|builder| {
+            builder.quiet -= 1;
+            if let Some((_, remaining)) = &res {
+                let (start, end) = (builder.offset(input), builder.offset(remaining));
+                builder.token(self.1.clone(), start, end);
+            }
+        });
+        res
+    }
+}
+
+impl<'a, T, P> Parser<'a, T> for Token<P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        self.run(input, || self.0.parse(input))
+    }
+}
+
+#[derive(Clone)]
+pub struct SyntaxTree<P>(P, Kind);
+
+impl<'a, T, P> Parser<'a, (T, SyntaxNode)> for SyntaxTree<P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<((T, SyntaxNode), &'a str)> {
+        let depth = BUILDERS.with(|v| {
+            let mut builders = v.borrow_mut();
+            builders.push(Builder {
+                len: input.len(),
+                events: vec![],
+                quiet: 0,
+            });
+            builders.len() - 1
+        });
+        BUILDING.fetch_add(1, Ordering::Relaxed);
+        let guard = BuilderGuard(depth);
+        let res = self.0.parse(input);
+        let builder = BUILDERS.with(|v| v.borrow_mut().pop()).unwrap();
+        drop(guard);
+
+        let (a, remaining) = res?;
+        let end = input.len() - remaining.len();
+        let green = builder.build(self.1.clone(), input, end);
+        Some(((a, SyntaxNode::new_root(Rc::new(green))), remaining))
+    }
+}
+
+/// match p, also build a lossless syntax tree of what p consumes
+///
+/// nodes come from p.node(kind) and tokens from p.token(kind), char and string (whose kind is their text), satisfy,
+/// take_while, digit, integer, float and string_literal (SATISFY, TAKE_WHILE...). Whitespaces and comments skipped by lexeme or Trivia
+/// become WHITESPACE and COMMENT tokens, anything else consumed becomes TEXT tokens, so printing the tree gives back
+/// exactly what p consumed.
+pub fn syntax_tree<'a, T, P, K>(kind: K, p: P) -> SyntaxTree<P>
+where
+    P: Parser<'a, T>,
+    K: Into<Kind>,
+{
+    SyntaxTree(p, kind.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        any, char, checked_integer, digit, eof, float, integer, lexer, raw_string_literal, satisfy,
+        string_literal, symbol, take_while1, trivia, whitespaces,
+    };
+
+    fn kinds(node: &SyntaxNode) -> Vec<(String, String)> {
+        node.tokens()
+            .iter()
+            .map(|v| (v.kind().to_owned(), v.text().to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn syntax_tree_round_trip() {
+        fn expr(input: &str) -> Option<(i64, &str)> {
+            let atom = integer
+                .map(|v: i64| v)
+                .token("number")
+                .lexeme()
+                .choice(expr.between(symbol("("), symbol(")")))
+                .node("atom");
+            atom.chain_left1(symbol("+").map(|_| |a, b| a + b))
+                .node("expr")
+                .parse(input)
+        }
+
+        let parser = syntax_tree("root", whitespaces.right(expr).left(eof));
+        let input = " 1 +( 2+3 )  + 4\n";
+        let ((v, tree), _) = parser.parse(input).unwrap();
+        assert_eq!(v, 10);
+        assert_eq!(tree.to_string(), input);
+        assert_eq!(tree.kind(), "root");
+        assert_eq!(
+            tree.span(),
+            Span {
+                start: 0,
+                end: input.len()
+            }
+        );
+
+        let expr = &tree.child_nodes()[0];
+        assert_eq!(expr.kind(), "expr");
+        assert_eq!(expr.text(), "1 +( 2+3 )  + 4\n");
+        let atoms = expr.child_nodes();
+        assert_eq!(atoms.len(), 3);
+        assert_eq!(atoms[1].text(), "( 2+3 )  ");
+        assert_eq!(atoms[1].span(), Span { start: 4, end: 13 });
+        assert_eq!(atoms[1].parent().unwrap().kind(), "expr");
+
+        let tokens = kinds(&atoms[1]);
+        assert_eq!(
+            tokens,
+            vec![
+                ("(".to_owned(), "(".to_owned()),
+                (WHITESPACE.to_owned(), " ".to_owned()),
+                ("number".to_owned(), "2".to_owned()),
+                ("+".to_owned(), "+".to_owned()),
+                ("number".to_owned(), "3".to_owned()),
+                (WHITESPACE.to_owned(), " ".to_owned()),
+                (")".to_owned(), ")".to_owned()),
+                (WHITESPACE.to_owned(), "  ".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_tree_comments() {
+        let lexer = lexer(trivia().line_comment("#"));
+        let item = lexer.lexeme(digit.many1().map(|v: String| v).node("item"));
+        let parser = syntax_tree(
+            "list",
+            lexer.trivia().right(item.separated_by(lexer.symbol(","))),
+        );
+        let input = "# numbers\n1 , 23# last\n,4";
+        let ((v, tree), remaining) = parser.parse(input).unwrap();
+        assert_eq!((v.len(), remaining), (3, ""));
+        assert_eq!(tree.to_string(), input);
+
+        let tokens = tree.tokens();
+        assert_eq!(tokens[0].kind(), COMMENT);
+        assert_eq!(tokens[0].text(), "# numbers");
+        assert!(tokens[1].is_trivia());
+        assert_eq!((tokens[2].kind(), tokens[2].text()), (DIGIT, "1"));
+        assert_eq!(tokens[2].parent().kind(), "item");
+        assert_eq!(tokens[2].span(), Span { start: 10, end: 11 });
+    }
+
+    #[test]
+    fn syntax_tree_primitive_kinds() {
+        let parser = syntax_tree(
+            "root",
+            satisfy(|c| c.is_alphabetic())
+                .lexeme()
+                .right(take_while1(|c| c.is_alphabetic()).lexeme())
+                .right(digit.lexeme())
+                .right(integer.map(|v: i64| v).lexeme())
+                .right(float::<f64>().lexeme())
+                .right(checked_integer::<i32>())
+                .right(any),
+        );
+        let input = "x yz 1 - 23 4.5 -6!";
+        let ((v, tree), _) = parser.parse(input).unwrap();
+        assert_eq!(v, '!');
+        assert_eq!(tree.to_string(), input);
+        let tokens: Vec<_> = tree
+            .tokens()
+            .iter()
+            .filter(|v| !v.is_trivia())
+            .map(|v| (v.kind().to_owned(), v.text().to_owned()))
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                (SATISFY, "x"),
+                (TAKE_WHILE, "yz"),
+                (DIGIT, "1"),
+                (INTEGER, "- 23"),
+                (FLOAT, "4.5"),
+                (INTEGER, "-6"),
+                (TEXT, "!"),
+            ]
+            .iter()
+            .map(|&(kind, text)| (kind.to_owned(), text.to_owned()))
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn syntax_tree_string_token() {
+        let parser = syntax_tree(
+            "root",
+            string_literal()
+                .lexeme()
+                .right(raw_string_literal().lexeme())
+                .right(any),
+        );
+        let input = r##""a\"b" r#"c"# !"##;
+        let ((_, tree), _) = parser.parse(input).unwrap();
+        assert_eq!(tree.to_string(), input);
+        let tokens: Vec<_> = tree
+            .tokens()
+            .iter()
+            .filter(|v| !v.is_trivia())
+            .map(|v| (v.kind().to_owned(), v.text().to_owned()))
+            .collect();
+        assert_eq!(
+            tokens,
+            [(STRING, r#""a\"b""#), (STRING, r##"r#"c"#"##), (TEXT, "!")]
+                .iter()
+                .map(|&(kind, text)| (kind.to_owned(), text.to_owned()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn syntax_tree_backtracking() {
+        // 第一个分支吃了a之后失败，树里不能留下它
+        let parser = syntax_tree(
+            "root",
+            char('a')
+                .right(char('b'))
+                .node("ab")
+                .choice(char('a').right(char('c')).node("ac"))
+                .many()
+                .map(|v: Vec<char>| v),
+        );
+        let ((v, tree), remaining) = parser.parse("acabad").unwrap();
+        assert_eq!((v, remaining), (vec!['c', 'b'], "ad"));
+        assert_eq!(tree.to_string(), "acab");
+        let nodes: Vec<_> = tree
+            .child_nodes()
+            .iter()
+            .map(|v| v.kind().to_owned())
+            .collect();
+        assert_eq!(nodes, vec!["ac", "ab"]);
+
+        let parser = syntax_tree("root", char('a').look_ahead().right(char('a')));
+        let ((_, tree), _) = parser.parse("a").unwrap();
+        assert_eq!(kinds(&tree), vec![("a".to_owned(), "a".to_owned())]);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::xid::is_xid_continue;
+use crate::Parser;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+/// which floating point literal syntax to accept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// -0.5e10, no leading +, no leading zeros, no 1. or .5
+    Json,
+    /// 1_000.5e-3 and 1., digits can be separated by _
+    Rust,
+    /// .5, 1. and an optional f, F, l or L suffix like 1.5f
+    C,
+}
+
+#[derive(Clone)]
+pub struct Float<T> {
+    format: FloatFormat,
+    special: bool,
+    phantom: PhantomData<T>,
+}
+
+/// a floating point number parsed into T
+///
+/// T can be f32, f64 or anything else implementing FromStr, the digits are handed to T::from_str so the result is
+/// correctly rounded. Rust syntax by default.
+pub fn float<T>() -> Float<T>
+where
+    T: FromStr,
+{
+    Float {
+        format: FloatFormat::Rust,
+        special: false,
+        phantom: PhantomData,
+    }
+}
+
+impl<T> Float<T> {
+    pub fn format(self, format: FloatFormat) -> Self {
+        Self { format, ..self }
+    }
+
+    /// also accept inf, infinity and nan (ignoring case), but not the start of a longer word like information
+    pub fn special(self, special: bool) -> Self {
+        Self { special, ..self }
+    }
+
+    // 返回数字部分的长度和整个字面量的长度，两者只差一个C的后缀
+    fn scan(&self, input: &str) -> Option<(usize, usize)> {
+        let bytes = input.as_bytes();
+        let at = |i: usize| bytes.get(i).copied().unwrap_or(0);
+        let separator = self.format == FloatFormat::Rust;
+        let mut i = 0;
+
+        if at(i) == b'-' || (at(i) == b'+' && self.format != FloatFormat::Json) {
+            i += 1;
+        }
+
+        if self.special {
+            for word in ["infinity", "inf", "nan"].iter() {
+                let end = i + word.len();
+                if let Some(v) = input.get(i..end) {
+                    // 和keyword一样，后面不能还是标识符的一部分
+                    if v.eq_ignore_ascii_case(word) && !input[end..].starts_with(is_xid_continue) {
+                        return Some((end, end));
+                    }
+                }
+            }
+        }
+
+        let integer = if self.format == FloatFormat::Json && at(i) == b'0' {
+            1 // JSON里0后面不能再跟数字
+        } else {
+            digits(&input[i..], separator)
+        };
+        i += integer;
+
+        // C的后缀只能跟在真正的浮点数后面，1f和1L不是
+        let mut real = false;
+        let mut fraction = 0;
+        if at(i) == b'.' {
+            fraction = digits(&input[i + 1..], separator);
+            let next = at(i + 1);
+            let dangling = match self.format {
+                FloatFormat::Json => false,
+                // 和Rust的词法一样，1.后面是.或者标识符的话，点不算数字的一部分，比如1..2和1.max(2)
+                FloatFormat::Rust => {
+                    integer > 0 && next != b'.' && next != b'_' && !next.is_ascii_alphabetic()
+                }
+                FloatFormat::C => integer > 0,
+            };
+            if fraction > 0 || dangling {
+                i += 1 + fraction;
+                real = true;
+            }
+        }
+
+        if integer == 0 && (fraction == 0 || self.format != FloatFormat::C) {
+            return None;
+        }
+
+        if at(i) == b'e' || at(i) == b'E' {
+            let mut j = i + 1;
+            if at(j) == b'+' || at(j) == b'-' {
+                j += 1;
+            }
+            let exponent = digits(&input[j..], separator);
+            if exponent > 0 {
+                i = j + exponent;
+                real = true;
+            }
+        }
+
+        if self.format == FloatFormat::C && real && b"fFlL".contains(&at(i)) {
+            Some((i, i + 1))
+        } else {
+            Some((i, i))
+        }
+    }
+}
+
+// 开头至少要有一个数字，之后可以夹杂分隔符
+fn digits(input: &str, separator: bool) -> usize {
+    if !input.starts_with(|c: char| c.is_ascii_digit()) {
+        return 0;
+    }
+
+    input
+        .find(|c: char| !(c.is_ascii_digit() || (separator && c == '_')))
+        .unwrap_or(input.len())
+}
+
+impl<'a, T> Parser<'a, T> for Float<T>
+where
+    T: FromStr,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        crate::budget::primitive(|| {
+            let (number, literal) = self.scan(input)?;
+            let number = &input[..number];
+            // 只有带分隔符的时候才需要分配内存
+            let v = if number.contains('_') {
+                number.replace('_', "").parse().ok()?
+            } else {
+                number.parse().ok()?
+            };
+            let remaining = &input[literal..];
+            crate::cst::token(|| crate::cst::FLOAT.into(), input, remaining);
+            Some((v, remaining))
+        })
+    }
+}
+
+/// the slice of a floating point literal in Rust syntax, without converting it
+pub fn recognize_float(input: &str) -> Option<(&str, &str)> {
+    let (_, literal) = float::<f64>().scan(input)?;
+    Some(input.split_at(literal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{char, recognize};
+
+    #[test]
+    fn float_rust() {
+        let parser = float::<f64>();
+        assert_eq!(dbg!(parser.parse("1.5")), Some((1.5, "")));
+        assert_eq!(dbg!(parser.parse("-1_000.25e-2x")), Some((-10.0025, "x")));
+        assert_eq!(dbg!(parser.parse("+2E3")), Some((2000.0, "")));
+        assert_eq!(dbg!(parser.parse("42")), Some((42.0, "")));
+        assert_eq!(dbg!(parser.parse("1.")), Some((1.0, "")));
+        assert_eq!(dbg!(parser.parse("1..2")), Some((1.0, "..2")));
+        assert_eq!(dbg!(parser.parse("1.max(2)")), Some((1.0, ".max(2)")));
+        assert_eq!(dbg!(parser.parse("1e")), Some((1.0, "e")));
+        assert_eq!(dbg!(parser.parse(".5")), None);
+        assert_eq!(dbg!(parser.parse("_1")), None);
+        assert_eq!(dbg!(parser.parse("inf")), None);
+    }
+
+    #[test]
+    fn float_json() {
+        let parser = float::<f64>().format(FloatFormat::Json);
+        assert_eq!(dbg!(parser.parse("-0.5e10,")), Some((-0.5e10, ",")));
+        assert_eq!(dbg!(parser.parse("0")), Some((0.0, "")));
+        assert_eq!(dbg!(parser.parse("012")), Some((0.0, "12")));
+        assert_eq!(dbg!(parser.parse("1.")), Some((1.0, ".")));
+        assert_eq!(dbg!(parser.parse("1_0")), Some((1.0, "_0")));
+        assert_eq!(dbg!(parser.parse("+1")), None);
+        assert_eq!(dbg!(parser.parse(".5")), None);
+    }
+
+    #[test]
+    fn float_c() {
+        let parser = float::<f32>().format(FloatFormat::C);
+        assert_eq!(dbg!(parser.parse("1.5f;")), Some((1.5, ";")));
+        assert_eq!(dbg!(parser.parse(".5")), Some((0.5, "")));
+        assert_eq!(dbg!(parser.parse("1.e2L")), Some((100.0, "")));
+        assert_eq!(dbg!(parser.parse(".")), None);
+        assert_eq!(dbg!(parser.parse("-.e1")), None);
+        assert_eq!(dbg!(parser.parse("1e3f")), Some((1000.0, "")));
+        assert_eq!(dbg!(parser.parse("1f")), Some((1.0, "f")));
+        assert_eq!(dbg!(parser.parse("1L")), Some((1.0, "L")));
+    }
+
+    #[test]
+    fn float_special() {
+        let parser = float::<f64>().special(true);
+        assert_eq!(dbg!(parser.parse("-inf")), Some((f64::NEG_INFINITY, "")));
+        assert_eq!(dbg!(parser.parse("Infinity!")), Some((f64::INFINITY, "!")));
+        assert!(parser.parse("NaN").unwrap().0.is_nan());
+        assert_eq!(dbg!(parser.parse("1.5")), Some((1.5, "")));
+        assert_eq!(dbg!(parser.parse("inf)")), Some((f64::INFINITY, ")")));
+        assert_eq!(dbg!(parser.parse("information")), None);
+        assert_eq!(dbg!(parser.parse("infinity_")), None);
+        assert_eq!(dbg!(parser.parse("-nanx")), None);
+    }
+
+    #[test]
+    fn float_correctly_rounded() {
+        let parser = float::<f64>();
+        assert_eq!(dbg!(parser.parse("0.1")), Some((0.1, "")));
+        assert_eq!(
+            dbg!(parser.parse("2.2250738585072011e-308")),
+            Some((2.225_073_858_507_201e-308, ""))
+        );
+        assert_eq!(
+            dbg!(float::<f32>().parse("16777217")),
+            Some((16_777_216.0, ""))
+        );
+    }
+
+    #[test]
+    fn float_recognize() {
+        assert_eq!(dbg!(recognize_float("-1_0.5e3f")), Some(("-1_0.5e3", "f")));
+        let parser = recognize(float::<f32>().format(FloatFormat::C)).left(char(';'));
+        assert_eq!(dbg!(parser.parse("1.5f;")), Some(("1.5f", "")));
+    }
+}
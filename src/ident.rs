@@ -0,0 +1,205 @@
+use crate::xid::{is_xid_continue, is_xid_start};
+use crate::Parser;
+use std::borrow::Cow;
+
+// 标准的Unicode标识符（UAX #31）：XID_Start或者_开头，后面是XID_Continue
+fn is_start(c: char, ascii: bool) -> bool {
+    if ascii {
+        c == '_' || c.is_ascii_alphabetic()
+    } else {
+        c == '_' || is_xid_start(c)
+    }
+}
+
+fn is_continue(c: char, ascii: bool) -> bool {
+    if ascii {
+        c == '_' || c.is_ascii_alphanumeric()
+    } else {
+        is_xid_continue(c)
+    }
+}
+
+// 整个单词有多长，开头不是标识符字符就是0
+fn word_len(input: &str, ascii: bool) -> usize {
+    match input.chars().next() {
+        Some(c) if is_start(c, ascii) => input
+            .find(|c: char| !is_continue(c, ascii))
+            .unwrap_or(input.len()),
+        _ => 0,
+    }
+}
+
+#[derive(Clone)]
+pub struct Identifier<'b> {
+    ascii: bool,
+    reserved: Vec<Cow<'b, str>>,
+}
+
+/// a letter or _, then letters, digits or _, return the whole identifier
+///
+/// follows Unicode identifiers: the first character is XID_Start or _, the rest are XID_Continue, so `变量1` and
+/// `é` written with a combining accent are identifiers.
+pub fn identifier<'b>() -> Identifier<'b> {
+    Identifier {
+        ascii: false,
+        reserved: vec![],
+    }
+}
+
+impl<'b> Identifier<'b> {
+    /// only accept ASCII letters, digits and _
+    pub fn ascii(self, ascii: bool) -> Self {
+        Self { ascii, ..self }
+    }
+
+    /// fail on these words, so keywords are not identifiers
+    pub fn reserved<I, S>(mut self, words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<Cow<'b, str>>,
+    {
+        self.reserved.extend(words.into_iter().map(Into::into));
+        self
+    }
+
+    /// a keyword that follows the same rule of what an identifier character is
+    pub fn keyword<S>(&self, word: S) -> Keyword<'b>
+    where
+        S: Into<Cow<'b, str>>,
+    {
+        Keyword {
+            word: word.into(),
+            ascii: self.ascii,
+        }
+    }
+}
+
+impl<'a, 'b> Parser<'a, &'a str> for Identifier<'b> {
+    fn parse(&self, input: &'a str) -> Option<(&'a str, &'a str)> {
+        crate::budget::primitive(|| {
+            let len = word_len(input, self.ascii);
+            if len == 0 {
+                return None;
+            }
+
+            let (word, remaining) = input.split_at(len);
+            // 先吃完整个单词再查表，所以iffy不会因为if是关键字而失败
+            if self.reserved.iter().any(|v| v == word) {
+                return None;
+            }
+            Some((word, remaining))
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Keyword<'b> {
+    word: Cow<'b, str>,
+    ascii: bool,
+}
+
+impl<'a, 'b> Parser<'a, &'a str> for Keyword<'b> {
+    fn parse(&self, input: &'a str) -> Option<(&'a str, &'a str)> {
+        crate::budget::primitive(|| {
+            let remaining = match input.strip_prefix(self.word.as_ref()) {
+                Some(v) => v,
+                None => {
+                    if self.word.len() > input.len() && self.word.starts_with(input) {
+                        crate::complete::expect(|| self.word.to_string());
+                    }
+                    return None;
+                }
+            };
+            // ifx是标识符，不是if后面跟着x
+            if remaining.starts_with(|c: char| is_continue(c, self.ascii)) {
+                return None;
+            }
+            Some(input.split_at(self.word.len()))
+        })
+    }
+}
+
+/// match a particular word, fail if it is followed by an identifier character, so `if` doesn't match `ifx`
+pub fn keyword<'b, S>(word: S) -> Keyword<'b>
+where
+    S: Into<Cow<'b, str>>,
+{
+    Keyword {
+        word: word.into(),
+        ascii: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::char;
+
+    #[test]
+    fn identifier_unicode() {
+        let parser = identifier();
+        assert_eq!(dbg!(parser.parse("_foo1 = 2")), Some(("_foo1", " = 2")));
+        assert_eq!(dbg!(parser.parse("变量λ2+1")), Some(("变量λ2", "+1")));
+        assert_eq!(dbg!(parser.parse("1abc")), None);
+        assert_eq!(dbg!(parser.parse("")), None);
+
+        let parser = identifier().ascii(true);
+        assert_eq!(dbg!(parser.parse("abcλ")), Some(("abc", "λ")));
+        assert_eq!(dbg!(parser.parse("λ")), None);
+    }
+
+    #[test]
+    fn identifier_xid() {
+        let parser = identifier();
+        // e后面跟着组合用的重音符号U+0301
+        assert_eq!(
+            dbg!(parser.parse("cafe\u{301} = 1")),
+            Some(("cafe\u{301}", " = 1"))
+        );
+        // 波斯语里的ZWNJ
+        assert_eq!(
+            dbg!(parser.parse("\u{645}\u{6cc}\u{200c}\u{62e}\u{648}\u{627}\u{647}\u{645} x")),
+            Some((
+                "\u{645}\u{6cc}\u{200c}\u{62e}\u{648}\u{627}\u{647}\u{645}",
+                " x"
+            ))
+        );
+        assert_eq!(dbg!(parser.parse("a\u{200d}b")), Some(("a\u{200d}b", "")));
+        // _以外的连接符号
+        assert_eq!(dbg!(parser.parse("a\u{203f}b")), Some(("a\u{203f}b", "")));
+        // 组合符号不能开头；ⸯ是字母，但不是XID_Start
+        assert_eq!(dbg!(parser.parse("\u{301}a")), None);
+        assert_eq!(dbg!(parser.parse("\u{200c}a")), None);
+        assert_eq!(dbg!(parser.parse("\u{2e2f}")), None);
+        assert_eq!(dbg!(keyword("if").parse("if\u{301}")), None);
+    }
+
+    #[test]
+    fn identifier_reserved() {
+        let parser = identifier().reserved(vec!["if", "else"]);
+        assert_eq!(dbg!(parser.parse("if x")), None);
+        assert_eq!(dbg!(parser.parse("iffy x")), Some(("iffy", " x")));
+        assert_eq!(dbg!(parser.parse("else_")), Some(("else_", "")));
+
+        let keywords = vec![String::from("let")];
+        let parser = identifier().reserved(keywords);
+        assert_eq!(dbg!(parser.parse("let")), None);
+    }
+
+    #[test]
+    fn keyword_boundary() {
+        let parser = keyword("if");
+        assert_eq!(dbg!(parser.parse("if(x)")), Some(("if", "(x)")));
+        assert_eq!(dbg!(parser.parse("if")), Some(("if", "")));
+        assert_eq!(dbg!(parser.parse("ifx")), None);
+        assert_eq!(dbg!(parser.parse("if_")), None);
+        assert_eq!(dbg!(parser.parse("if1")), None);
+        assert_eq!(dbg!(parser.parse("ifλ")), None);
+
+        let ident = identifier().ascii(true).reserved(vec!["if"]);
+        let parser = ident.keyword("if").left(char(' ')).right(ident.clone());
+        assert_eq!(dbg!(parser.parse("if λ")), None);
+        assert_eq!(dbg!(parser.parse("if x")), Some(("x", "")));
+        assert_eq!(dbg!(ident.keyword("if").parse("ifλ")), Some(("if", "λ")));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::cst::{GreenElement, GreenNode, Kind};
+use crate::{syntax_tree, Parser, Span, SyntaxNode};
+use std::rc::Rc;
+
+/// replace the bytes in span of the old text with text
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub span: Span,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn new<S>(start: usize, end: usize, text: S) -> Self
+    where
+        S: Into<String>,
+    {
+        assert!(start <= end, "edit ends before it starts");
+        Edit {
+            span: Span { start, end },
+            text: text.into(),
+        }
+    }
+
+    /// the text after this edit, None if span is out of text or not on character boundaries
+    pub fn apply(&self, text: &str) -> Option<String> {
+        let (before, after) = (text.get(..self.span.start)?, text.get(self.span.end..)?);
+        let mut res = String::with_capacity(text.len() + self.text.len());
+        res.push_str(before);
+        res.push_str(&self.text);
+        res.push_str(after);
+        Some(res)
+    }
+}
+
+// 解析一整段文本，必须全部吃完，返回以kind为根的树
+type Rule<'p> = Box<dyn Fn(&str) -> Option<Rc<GreenNode>> + 'p>;
+
+fn rule<'p, T, F>(kind: Kind, f: F) -> Rule<'p>
+where
+    F: Fn(&str) -> Option<(T, &str)> + 'p,
+{
+    Box::new(move |input| {
+        let ((_, tree), remaining) = syntax_tree(kind.clone(), &f).parse(input)?;
+        if !remaining.is_empty() {
+            return None;
+        }
+
+        // 规则自己用了node(kind)的话，根下面就只有这一个节点，拿出来用
+        let green = tree.green();
+        match green.children() {
+            [GreenElement::Node(v)] if v.kind() == kind => Some(v.clone()),
+            _ => Some(green.clone()),
+        }
+    })
+}
+
+// 最左和最右的token的长度，改动碰到它们的话，节点的边界可能会变
+fn first_token_len(green: &GreenNode) -> Option<usize> {
+    green.children().iter().find_map(|v| match v {
+        GreenElement::Node(v) => first_token_len(v),
+        GreenElement::Token(v) => Some(v.text().len()),
+    })
+}
+
+fn last_token_len(green: &GreenNode) -> Option<usize> {
+    green.children().iter().rev().find_map(|v| match v {
+        GreenElement::Node(v) => last_token_len(v),
+        GreenElement::Token(v) => Some(v.text().len()),
+    })
+}
+
+// 沿着path换掉一个节点，其他的子树原样共享
+fn replace(green: &GreenNode, path: &[usize], new: Rc<GreenNode>) -> Rc<GreenNode> {
+    let (i, path) = match path.split_first() {
+        Some(v) => v,
+        None => return new,
+    };
+    let mut children = green.children().to_vec();
+    if let GreenElement::Node(child) = &children[*i] {
+        children[*i] = GreenElement::Node(replace(child, path, new));
+    }
+    Rc::new(GreenNode::new(green.kind().to_owned(), children))
+}
+
+/// reparse only the smallest node around an edit instead of the whole text
+///
+/// each rule parses the text of nodes of a kind. After an edit, the innermost node with a rule that contains the edit
+/// (without touching its first or last token) is reparsed alone and spliced into the old tree, everything else is
+/// shared with the old tree. Falls back to parsing the whole text with the root rule. Rules should not depend on
+/// anything outside the node, like with_pos or user state.
+pub struct Reparser<'p> {
+    root: Rule<'p>,
+    rules: Vec<(Kind, Rule<'p>)>,
+}
+
+impl<'p> Reparser<'p> {
+    /// root parses the whole text into a tree whose root is of kind
+    pub fn new<K, T, F>(kind: K, root: F) -> Self
+    where
+        K: Into<Kind>,
+        F: Fn(&str) -> Option<(T, &str)> + 'p,
+    {
+        Reparser {
+            root: rule(kind.into(), root),
+            rules: vec![],
+        }
+    }
+
+    /// f parses exactly the text of a node of kind, so such nodes can be reparsed alone
+    pub fn rule<K, T, F>(mut self, kind: K, f: F) -> Self
+    where
+        K: Into<Kind>,
+        F: Fn(&str) -> Option<(T, &str)> + 'p,
+    {
+        let kind = kind.into();
+        self.rules.push((kind.clone(), rule(kind, f)));
+        self
+    }
+
+    /// parse the whole text, fail if it's not all consumed
+    pub fn parse(&self, text: &str) -> Option<SyntaxNode> {
+        (self.root)(text).map(SyntaxNode::new_root)
+    }
+
+    /// the tree of old with edit applied, None if it doesn't parse or edit doesn't fit old's text
+    pub fn reparse(&self, old: &SyntaxNode, edit: &Edit) -> Option<SyntaxNode> {
+        let root = old.green();
+        let (start, end) = (edit.span.start, edit.span.end);
+
+        // 从根往下找包含改动的节点，记下路径，越往后越深
+        let mut candidates = vec![];
+        let mut path = vec![];
+        let mut green = root.clone();
+        let mut offset = 0;
+        'outer: loop {
+            let mut child_offset = offset;
+            for (i, child) in green.children().iter().enumerate() {
+                let len = child.text_len();
+                if let GreenElement::Node(node) = child {
+                    let first = first_token_len(node).unwrap_or(0);
+                    let last = last_token_len(node).unwrap_or(0);
+                    if child_offset + first <= start && end + last <= child_offset + len {
+                        path.push(i);
+                        candidates.push((path.clone(), node.clone(), child_offset));
+                        green = node.clone();
+                        offset = child_offset;
+                        continue 'outer;
+                    }
+                }
+                child_offset += len;
+            }
+            break;
+        }
+
+        for (path, node, offset) in candidates.into_iter().rev() {
+            let rule = match self.rules.iter().find(|(kind, _)| kind == node.kind()) {
+                Some((_, rule)) => rule,
+                None => continue,
+            };
+            let local = Edit {
+                span: Span {
+                    start: start - offset,
+                    end: end - offset,
+                },
+                text: edit.text.clone(),
+            };
+            if let Some(new) = rule(&local.apply(&node.to_string())?) {
+                return Some(SyntaxNode::new_root(replace(root, &path, new)));
+            }
+        }
+
+        self.parse(&edit.apply(&old.text())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eof, identifier, symbol, whitespaces};
+    use std::cell::Cell;
+
+    fn block(input: &str) -> Option<((), &str)> {
+        let item = identifier()
+            .token("ident")
+            .lexeme()
+            .map(|_| ())
+            .choice(block);
+        item.many()
+            .map(|_: Vec<()>| ())
+            .between(symbol("{"), symbol("}"))
+            .node("block")
+            .parse(input)
+    }
+
+    fn file(input: &str) -> Option<((), &str)> {
+        whitespaces
+            .right(block.many().map(|_: Vec<()>| ()))
+            .left(eof)
+            .parse(input)
+    }
+
+    #[test]
+    fn reparse_inner_block() {
+        let (files, blocks) = (Cell::new(0), Cell::new(0));
+        let reparser = Reparser::new("file", |input| {
+            files.set(files.get() + 1);
+            file(input)
+        })
+        .rule("block", |input| {
+            blocks.set(blocks.get() + 1);
+            block(input)
+        });
+
+        let text = "{ a { b } c }\n{ d }";
+        let old = reparser.parse(text).unwrap();
+        assert_eq!(old.to_string(), text);
+
+        // 把b改成bee，只需要重新解析{ b }
+        let edit = Edit::new(6, 7, "bee");
+        let new = reparser.reparse(&old, &edit).unwrap();
+        assert_eq!(new.to_string(), edit.apply(text).unwrap());
+        assert_eq!((files.get(), blocks.get()), (1, 1));
+        assert_eq!(
+            new.green().as_ref(),
+            reparser
+                .parse(&edit.apply(text).unwrap())
+                .unwrap()
+                .green()
+                .as_ref()
+        );
+
+        // 没有改到的第二个块和旧的树共享
+        let (old_second, new_second) = (&old.child_nodes()[1], &new.child_nodes()[1]);
+        assert!(Rc::ptr_eq(old_second.green(), new_second.green()));
+        assert_eq!(new_second.span(), Span { start: 16, end: 21 });
+    }
+
+    #[test]
+    fn reparse_fallback() {
+        let files = Cell::new(0);
+        let reparser = Reparser::new("file", |input| {
+            files.set(files.get() + 1);
+            file(input)
+        })
+        .rule("block", block);
+        let text = "{ a { b } c }";
+        let old = reparser.parse(text).unwrap();
+
+        // 改到了里面那个块的边界，外面的块也解析不了，只能整个重新解析
+        let edit = Edit::new(4, 4, "} {");
+        let new = reparser.reparse(&old, &edit).unwrap();
+        assert_eq!(new.to_string(), "{ a } {{ b } c }");
+        assert_eq!(files.get(), 2);
+        assert_eq!(new.child_nodes().len(), 2);
+
+        // 多了一个}，整个都解析不了
+        assert!(reparser.reparse(&old, &Edit::new(7, 7, "}")).is_none());
+        assert_eq!(files.get(), 3);
+    }
+
+    #[test]
+    fn edit_out_of_bounds() {
+        let text = "{ λ }";
+        assert_eq!(Edit::new(2, 4, "x").apply(text), Some("{ x }".to_owned()));
+        assert_eq!(Edit::new(6, 6, "x").apply(text), Some("{ λ }x".to_owned()));
+        assert_eq!(Edit::new(3, 4, "x").apply(text), None);
+        assert_eq!(Edit::new(6, 9, "x").apply(text), None);
+        assert_eq!(Edit::new(7, 7, "x").apply(text), None);
+
+        let reparser = Reparser::new("file", file).rule("block", block);
+        let old = reparser.parse("{ a }").unwrap();
+        assert!(reparser.reparse(&old, &Edit::new(9, 9, "b")).is_none());
+        assert!(reparser.reparse(&old, &Edit::new(2, 9, "b")).is_none());
+    }
+}
@@ -0,0 +1,419 @@
+use crate::position::{anchored_text, offset_of, position_of, starts_line, Position};
+use crate::state;
+use crate::Parser;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// a block ended at a line whose indentation matches neither the block nor anything around it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndentError {
+    pub line: usize,
+    /// the column of the block
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl fmt::Display for IndentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bad indentation at line {}: expected column {}, found column {}",
+            self.line, self.expected, self.actual
+        )
+    }
+}
+
+impl Error for IndentError {}
+
+thread_local! {
+    // with_pos记下来的参照位置，嵌套的with_pos会压栈
+    static REFERENCE: RefCell<Vec<Position>> = const { RefCell::new(Vec::new()) };
+    // parse_with_indentation的时候，块停下来的地方缩进不对就记在这里，只留最远的那个
+    static ERRORS: RefCell<Vec<Option<(usize, IndentError)>>> = const { RefCell::new(Vec::new()) };
+}
+
+struct ErrorsGuard;
+
+impl Drop for ErrorsGuard {
+    fn drop(&mut self) {
+        ERRORS.with(|v| v.borrow_mut().pop());
+    }
+}
+
+pub(crate) fn with_indent_errors<'a, T, F>(f: F) -> Result<Option<(T, &'a str)>, IndentError>
+where
+    F: FnOnce() -> Option<(T, &'a str)>,
+{
+    ERRORS.with(|v| v.borrow_mut().push(None));
+    let guard = ErrorsGuard;
+    let res = f();
+    let error = ERRORS.with(|v| v.borrow_mut().last_mut().unwrap().take());
+    drop(guard);
+    match (error, res) {
+        // 成功了而且已经越过了那个地方，说明别的东西接受了这样的缩进
+        (Some((offset, _)), Some((v, remaining))) if offset_of(remaining) > offset => {
+            Ok(Some((v, remaining)))
+        }
+        (Some((_, error)), _) => Err(error),
+        (None, res) => Ok(res),
+    }
+}
+
+// 块在input这里停下来了，看看是不是缩进的问题
+fn block_end(input: &str, reference: Position) {
+    if ERRORS.with(|v| v.borrow().is_empty()) || input.is_empty() || !starts_line(input) {
+        return;
+    }
+
+    let current = position_of(input);
+    ERRORS.with(|v| {
+        let mut errors = v.borrow_mut();
+        let error = errors.last_mut().unwrap();
+        let pending = error.map(|(offset, _)| offset);
+        if current.column == reference.column {
+            // 和这个块对齐，里面的块在这里停下是正常的，只是这一项parse不了
+            if pending == Some(current.offset) {
+                *error = None;
+            }
+        } else if pending.is_none_or(|v| v < current.offset) {
+            // 同一个地方里面的块先记，expected是离它最近的那个块
+            *error = Some((
+                current.offset,
+                IndentError {
+                    line: current.line,
+                    expected: reference.column,
+                    actual: current.column,
+                },
+            ));
+        }
+    });
+}
+
+// 没有with_pos的时候就以开头为参照
+fn reference() -> Position {
+    REFERENCE
+        .with(|v| v.borrow().last().copied())
+        .unwrap_or(Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        })
+}
+
+struct ReferenceGuard;
+
+impl Drop for ReferenceGuard {
+    fn drop(&mut self) {
+        REFERENCE.with(|v| v.borrow_mut().pop());
+    }
+}
+
+pub(crate) fn with_reference<R, F>(input: &str, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    anchored_text(input, || {
+        REFERENCE.with(|v| v.borrow_mut().push(position_of(input)));
+        let _guard = ReferenceGuard;
+        f()
+    })
+}
+
+// 比较当前位置和参照位置，满足条件就成功，不吃输入
+fn check<F>(input: &str, f: F) -> Option<((), &str)>
+where
+    F: FnOnce(Position, Position) -> bool,
+{
+    if anchored_text(input, || f(position_of(input), reference())) {
+        Some(((), input))
+    } else {
+        None
+    }
+}
+
+#[derive(Clone)]
+pub struct WithPos<P>(pub(crate) P);
+
+impl<'a, T, P> Parser<'a, T> for WithPos<P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        with_reference(input, || self.0.parse(input))
+    }
+}
+
+/// match p, where p starts becomes the reference position for check_indent, indented and same_line inside p
+pub fn with_pos<'a, T, P>(p: P) -> WithPos<P>
+where
+    P: Parser<'a, T>,
+{
+    WithPos(p)
+}
+
+/// succeed if the current column is the same as the reference column, consume no input
+pub fn check_indent(input: &str) -> Option<((), &str)> {
+    check(input, |current, reference| {
+        current.column == reference.column
+    })
+}
+
+/// succeed if the current column is to the right of the reference column, consume no input
+pub fn indented(input: &str) -> Option<((), &str)> {
+    check(input, |current, reference| {
+        current.column > reference.column
+    })
+}
+
+/// succeed if the current line is the same as the reference line, consume no input
+pub fn same_line(input: &str) -> Option<((), &str)> {
+    check(input, |current, reference| current.line == reference.line)
+}
+
+/// succeed if on the reference line or indented, for things that continue on the next line
+pub fn same_or_indented(input: &str) -> Option<((), &str)> {
+    check(input, |current, reference| {
+        current.line == reference.line || current.column > reference.column
+    })
+}
+
+// 每一项都要和第一项对齐，缩进变了就结束
+fn block_items<'a, T, P>(p: &P, input: &'a str) -> Option<(Vec<T>, &'a str)>
+where
+    P: Parser<'a, T>,
+{
+    with_reference(input, || {
+        let reference = reference();
+        let mut input = input;
+        let mut res = vec![];
+
+        while let Some((a, remaining)) =
+            state::attempt(|| check_indent(input).and_then(|_| p.parse(input)))
+        {
+            if remaining.len() == input.len() {
+                crate::no_progress("Block");
+                break;
+            }
+            res.push(a);
+            input = remaining;
+        }
+        block_end(input, reference);
+
+        if res.is_empty() {
+            None
+        } else {
+            Some((res, input))
+        }
+    })
+}
+
+#[derive(Clone)]
+pub struct Block<P>(pub(crate) P);
+
+impl<'a, T, P> Parser<'a, Vec<T>> for Block<P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<(Vec<T>, &'a str)> {
+        block_items(&self.0, input)
+    }
+}
+
+/// 1 or more p, each one starting at the same column as the first one
+///
+/// lexeme skips newlines, so items built from lexemes line up by their first token.
+pub fn block<'a, T, P>(p: P) -> Block<P>
+where
+    P: Parser<'a, T>,
+{
+    Block(p)
+}
+
+#[derive(Clone)]
+pub struct IndentedBlock<T1, P1, P2>(pub(crate) P1, pub(crate) P2, PhantomData<T1>);
+
+impl<'a, T1, P1, T2, P2> Parser<'a, (T1, Vec<T2>)> for IndentedBlock<T1, P1, P2>
+where
+    P1: Parser<'a, T1>,
+    P2: Parser<'a, T2>,
+{
+    fn parse(&self, input: &'a str) -> Option<((T1, Vec<T2>), &'a str)> {
+        with_reference(input, || {
+            let (header, input) = self.0.parse(input)?;
+            // 头后面没有缩进的内容就是空的块
+            match indented(input).and_then(|_| block_items(&self.1, input)) {
+                Some((items, remaining)) => Some(((header, items), remaining)),
+                None => Some(((header, vec![]), input)),
+            }
+        })
+    }
+}
+
+/// header, then a block of items indented to the right of where header starts
+///
+/// like a Python `if x:` followed by its body. Items that are not indented are left for the caller.
+pub fn indented_block<'a, T1, P1, T2, P2>(header: P1, item: P2) -> IndentedBlock<T1, P1, P2>
+where
+    P1: Parser<'a, T1>,
+    P2: Parser<'a, T2>,
+{
+    IndentedBlock(header, item, PhantomData)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{char, eof, identifier, origin, symbol, take_while1, whitespaces};
+    use std::panic::catch_unwind;
+
+    #[derive(Debug, PartialEq)]
+    enum Entry {
+        Leaf(String, String),
+        Section(String, Vec<Entry>),
+    }
+
+    fn entry(input: &str) -> Option<(Entry, &str)> {
+        let key = identifier().lexeme().left(symbol(":"));
+        let value = take_while1(|c| c != '\n').lexeme();
+        // 值必须和键在同一行
+        let leaf = with_pos(key.clone().and_then(move |k: &str| {
+            same_line
+                .right(value.clone())
+                .map(move |v: &str| Entry::Leaf(k.to_owned(), v.trim_end().to_owned()))
+        }));
+        let section = indented_block(key, entry)
+            .map(|(k, items): (&str, Vec<Entry>)| Entry::Section(k.to_owned(), items));
+        leaf.choice(section).parse(input)
+    }
+
+    #[test]
+    fn indented_config() {
+        // 位置从整个输入的开头算，跳过的空行也算在行号里
+        let parser = origin(whitespaces.right(block(entry)).left(eof));
+        let input = "
+server:
+  host: a b
+  tls:
+    cert: x
+  port: 80
+client:
+  name: c
+";
+        assert_eq!(
+            dbg!(parser.parse(input)),
+            Some((
+                vec![
+                    Entry::Section(
+                        "server".to_owned(),
+                        vec![
+                            Entry::Leaf("host".to_owned(), "a b".to_owned()),
+                            Entry::Section(
+                                "tls".to_owned(),
+                                vec![Entry::Leaf("cert".to_owned(), "x".to_owned())]
+                            ),
+                            Entry::Leaf("port".to_owned(), "80".to_owned()),
+                        ]
+                    ),
+                    Entry::Section(
+                        "client".to_owned(),
+                        vec![Entry::Leaf("name".to_owned(), "c".to_owned())]
+                    ),
+                ],
+                ""
+            ))
+        );
+    }
+
+    #[test]
+    fn indented_config_bad_indent() {
+        let parser = origin(whitespaces.right(block(entry)).left(eof));
+        assert_eq!(dbg!(parser.parse("a:\n  b: 1\n   c: 2\n")), None);
+        assert_eq!(dbg!(parser.parse("a:\n  b: 1\n c: 2\n")), None);
+        assert_eq!(
+            dbg!(parser.parse("a:\nb: 1\n")),
+            Some((
+                vec![
+                    Entry::Section("a".to_owned(), vec![]),
+                    Entry::Leaf("b".to_owned(), "1".to_owned())
+                ],
+                ""
+            ))
+        );
+        assert_eq!(dbg!(parser.parse("a:\n  b:\n1\n")), None);
+    }
+
+    #[test]
+    fn indented_config_error() {
+        let parser = whitespaces.right(block(entry)).left(eof);
+        // 多缩进了一格
+        assert_eq!(
+            dbg!(parser.parse_with_indentation("a:\n  b: 1\n   c: 2\n")),
+            Err(IndentError {
+                line: 3,
+                expected: 3,
+                actual: 4
+            })
+        );
+        // 少缩进了一格，和外面也对不上
+        let error = parser
+            .parse_with_indentation("a:\n  b: 1\n c: 2\n")
+            .unwrap_err();
+        assert_eq!(
+            error,
+            IndentError {
+                line: 3,
+                expected: 3,
+                actual: 2
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "bad indentation at line 3: expected column 3, found column 2"
+        );
+
+        // 缩进没问题，只是别的地方不对
+        assert_eq!(
+            dbg!(parser.parse_with_indentation("a:\n  b:\n1\n")),
+            Ok(None)
+        );
+        assert_eq!(
+            dbg!(parser.parse_with_indentation("a:\n  b: 1\nc: 2\n")).map(|v| v.map(|v| v.0.len())),
+            Ok(Some(2))
+        );
+        // 缩进不对的地方被后面的parser接受了就不算
+        let parser = whitespaces.right(block(entry)).left(take_while1(|_| true));
+        assert_eq!(
+            dbg!(parser.parse_with_indentation("a:\n  b: 1\n   c: 2\n")).map(|v| v.map(|v| v.1)),
+            Ok(Some(""))
+        );
+    }
+
+    #[test]
+    fn same_line_and_indented() {
+        let word = identifier().lexeme();
+        let parser = origin(with_pos(
+            word.clone()
+                .right(same_or_indented.right(word.clone()).many()),
+        ));
+        assert_eq!(
+            dbg!(parser.parse("f a\n  b\nc")),
+            Some((vec!["a", "b"], "c"))
+        );
+        let parser = origin(with_pos(char('x').right(whitespaces).right(same_line)));
+        assert_eq!(dbg!(parser.parse("x  y")), Some(((), "y")));
+        assert_eq!(dbg!(parser.parse("x\ny")), None);
+    }
+
+    #[test]
+    fn indentation_popped_after_panic() {
+        let res = catch_unwind(|| {
+            let parser = block(with_pos(char('a').map(|_| -> char { panic!("boom") })));
+            parser.parse_with_indentation("a")
+        });
+        assert!(res.is_err());
+        assert!(ERRORS.with(|v| v.borrow().is_empty()));
+        assert!(REFERENCE.with(|v| v.borrow().is_empty()));
+    }
+}
@@ -1,15 +1,112 @@
+use std::borrow::Cow;
+use std::cell::Cell;
 use std::fmt::Debug;
+use std::io::Write;
 use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
 // use std::ops::BitOr; // 本来想实现p1 | p2这种的，无奈又遇到了unconstrained type parameter问题，暂时放一放
 use std::str::FromStr;
 
-pub trait Parser<T> {
+pub mod ambiguous;
+pub mod budget;
+pub mod complete;
+pub mod cst;
+pub mod float;
+pub mod ident;
+pub mod incremental;
+pub mod indent;
+pub mod literal;
+pub mod permutation;
+pub mod position;
+pub mod pratt;
+pub mod state;
+pub mod trace;
+pub mod trivia;
+mod xid;
+
+pub use ambiguous::{all_function, AllFunction, AllParser};
+pub use budget::BudgetExhausted;
+pub use complete::{completions, Label};
+pub use cst::{syntax_tree, SyntaxElement, SyntaxNode, SyntaxToken, SyntaxTree};
+pub use float::{float, recognize_float, Float, FloatFormat};
+pub use ident::{identifier, keyword, Identifier, Keyword};
+pub use incremental::{Edit, Reparser};
+pub use indent::{
+    block, check_indent, indented, indented_block, same_line, same_or_indented, with_pos, Block,
+    IndentError, IndentedBlock, WithPos,
+};
+pub use literal::{raw_string_literal, string_literal, StringLiteral};
+pub use permutation::{opt, permutation, Opt, Permutation, Permute};
+pub use position::{origin, position, Locate, Located, Origin, Position, Span, Spanned};
+pub use pratt::{pratt, Operator, Pratt};
+pub use state::{get_state, set_state, update_state, GetState, SetState, UpdateState};
+pub use trace::{set_trace, Trace};
+pub use trivia::{
+    block_comment, lexer, line_comment, trivia, BlockComment, Lexer, LineComment, Trivia,
+};
+
+pub trait Parser<'a, T> {
     // 去掉了: Sized约束。如果不去掉，会使得任何实现了Parser<T>的struct无法变成trait object。
     // 那么联想到Iterator是怎么实现的呢？Iterator有的方法是取self（比如map、zip这一类）、有的方法取&mut self（比如next）。
     // 方法就是不要在trait层面就约束Sized，而是到方法层面约束。在方法后面加where Self: Sized。
     // 虽然我还是不理解为什么Sized就不能变成dyn Trait……
 
-    fn parse<'a>(&self, input: &'a str) -> Option<(T, &'a str)>;
+    // 'a从parse方法上挪到了trait上，这样T就可以借用input了，比如take_until返回的&'a str。
+    // 如果要把parser存起来在任意输入上用（比如Box<dyn ...>），就写for<'a> Parser<'a, T>
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)>;
+
+    /// parse with a user state that get_state, set_state and update_state can reach, state is updated in place
+    ///
+    /// whenever a combinator backtracks (choice, optional, many, separated_by, look_ahead...) the changes made by what
+    /// it gave up are undone, so the state always matches the input that was actually consumed.
+    fn parse_with_state<S>(&self, state: &mut S, input: &'a str) -> Option<(T, &'a str)>
+    where
+        Self: Sized,
+        S: Clone + 'static,
+    {
+        // 状态放在thread local里，这样所有的parser都不用改签名
+        state::with_state(state, || self.parse(input))
+    }
+
+    /// parse, but give up after steps primitive parsers (char, string, satisfy...) have been tried
+    ///
+    /// for untrusted input that makes a grammar backtrack for a very long time. Err means the budget ran out, Ok is
+    /// the usual result.
+    fn parse_with_budget(
+        &self,
+        steps: usize,
+        input: &'a str,
+    ) -> Result<Option<(T, &'a str)>, BudgetExhausted>
+    where
+        Self: Sized,
+    {
+        budget::with_budget(steps, || self.parse(input))
+    }
+
+    /// parse from the start of input like origin, Err if a block or indented_block stopped at a line indented wrong
+    ///
+    /// a block ends at the first line not aligned with it, which usually makes the parse fail somewhere far away or
+    /// stop early. This reports the line instead, unless the parse succeeded past it.
+    fn parse_with_indentation(&self, input: &'a str) -> Result<Option<(T, &'a str)>, IndentError>
+    where
+        Self: Sized,
+    {
+        position::with_origin(input, || indent::with_indent_errors(|| self.parse(input)))
+    }
+
+    /// parse and write what every trace point inside did to sink, even when set_trace is off
+    ///
+    /// each line is written as soon as it happens, so a parse that hangs or panics still shows how far it got. Errors
+    /// from writing to sink are ignored.
+    fn parse_with_trace<W>(&self, sink: W, input: &'a str) -> Option<(T, &'a str)>
+    where
+        Self: Sized,
+        W: Write + 'static,
+    {
+        trace::with_trace(Box::new(sink), || {
+            position::with_origin(input, || self.parse(input))
+        })
+    }
 
     // fn and_then<T2, P2>(self, another: P2) -> AndThen<Self, P2>
     // where
@@ -19,6 +116,9 @@ pub trait Parser<T> {
     // }
 
     /// p*
+    ///
+    /// stop once p succeeds without consuming input (keeping that last result). In debug builds this panics instead,
+    /// because it's almost always a bug like `epsilon.many()`.
     fn many(self) -> Many<Self>
     where
         Self: Sized, // 第一次知道还有这种写法。从std的Iterator学过来的
@@ -26,7 +126,7 @@ pub trait Parser<T> {
         Many(self)
     } // 我以为many: Parser<char> -> Parser<String>是不可能的……
 
-    /// p+
+    /// p+, stop on p not consuming input like many
     fn many1(self) -> Many1<Self>
     where
         Self: Sized,
@@ -60,7 +160,7 @@ pub trait Parser<T> {
     where
         Self: Sized,
         F: Fn(T) -> P2,
-        P2: Parser<T2>,
+        P2: Parser<'a, T2>,
     {
         AndThen(self, f, PhantomData)
     }
@@ -73,11 +173,34 @@ pub trait Parser<T> {
         Count(self, n)
     }
 
+    /// p{m,n}, match p as many times as possible but no more than the range allows, fail if fewer than its start
+    ///
+    /// `p.repeat(2..=4)`, `p.repeat(1..)` and `p.repeat(..3)` all work. Like many, stop once p succeeds without
+    /// consuming input past the start of the range, and panic instead in debug builds.
+    fn repeat<R>(self, range: R) -> Repeat<Self>
+    where
+        Self: Sized,
+        R: RangeBounds<usize>,
+    {
+        let min = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n.checked_add(1).expect("empty range in repeat"),
+            Bound::Unbounded => 0,
+        };
+        let max = match range.end_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n.checked_sub(1).expect("empty range in repeat"),
+            Bound::Unbounded => usize::MAX,
+        };
+        assert!(min <= max, "empty range in repeat");
+        Repeat(self, min, max)
+    }
+
     /// p1 <* p2, match p1 then match p2, return what p1 matches
     fn left<T2, P2>(self, another: P2) -> Left<T, Self, T2, P2>
     where
         Self: Sized,
-        P2: Parser<T2>,
+        P2: Parser<'a, T2>,
     {
         Left(self, another, PhantomData)
     }
@@ -94,12 +217,58 @@ pub trait Parser<T> {
     fn between<T1, P1, T2, P2>(self, p1: P1, p2: P2) -> Left<T, Right<T1, P1, T, Self>, T2, P2>
     where
         Self: Sized,
-        P1: Parser<T1>,
-        P2: Parser<T2>,
+        P1: Parser<'a, T1>,
+        P2: Parser<'a, T2>,
     {
         p1.right(self).left(p2)
     }
 
+    /// match p, completions shows name instead of what p is made of
+    fn label<'b, S>(self, name: S) -> Label<'b, Self>
+    where
+        Self: Sized,
+        S: Into<Cow<'b, str>>,
+    {
+        Label(self, name.into())
+    }
+
+    /// match p, it becomes a node of kind in syntax_tree
+    fn node<K>(self, kind: K) -> cst::Node<Self>
+    where
+        Self: Sized,
+        K: Into<cst::Kind>,
+    {
+        cst::Node(self, kind.into())
+    }
+
+    /// match p, what it consumes becomes one token of kind in syntax_tree
+    fn token<K>(self, kind: K) -> cst::Token<Self>
+    where
+        Self: Sized,
+        K: Into<cst::Kind>,
+    {
+        cst::Token(self, kind.into())
+    }
+
+    /// match p, also return the byte offsets where it starts and ends
+    ///
+    /// offsets count from the start of the input given to origin, or from where the outermost spanned starts without
+    /// origin.
+    fn spanned(self) -> Spanned<Self>
+    where
+        Self: Sized,
+    {
+        Spanned(self)
+    }
+
+    /// match p, also return the line and column where it starts and ends, counted like spanned
+    fn located(self) -> Locate<Self>
+    where
+        Self: Sized,
+    {
+        Locate(self)
+    }
+
     /// match p, consume no input even if success
     fn look_ahead(self) -> LookAhead<Self>
     where
@@ -108,6 +277,24 @@ pub trait Parser<T> {
         LookAhead(self)
     }
 
+    /// match p only if another matches right after it, another consumes no input
+    fn followed_by<T2, P2>(self, another: P2) -> Left<T, Self, T2, LookAhead<P2>>
+    where
+        Self: Sized,
+        P2: Parser<'a, T2>,
+    {
+        self.left(LookAhead(another))
+    }
+
+    /// match p only if another doesn't match right after it, like `<` but not `<=`
+    fn not_followed_by<T2, P2>(self, another: P2) -> Left<T, Self, (), Not<T2, P2>>
+    where
+        Self: Sized,
+        P2: Parser<'a, T2>,
+    {
+        self.left(not(another))
+    }
+
     /// match p and then 0 or more spaces, return what p matches
     fn lexeme(self) -> Left<T, Self, (), Whitespaces>
     where
@@ -116,17 +303,39 @@ pub trait Parser<T> {
         self.left(Whitespaces)
     }
     // 和Haskell parsec的不一样，没考虑注释啥的，单纯就是空格
+    // 要跳过注释的话用lexeme_with(trivia().line_comment("#"))
 
-    /// match 0 or more p, separated by separator
+    /// match p and then skip whatever skipper matches, return what p matches
+    fn lexeme_with<T2, P2>(self, skipper: P2) -> Left<T, Self, T2, P2>
+    where
+        Self: Sized,
+        P2: Parser<'a, T2>,
+    {
+        self.left(skipper)
+    }
+
+    /// match 0 or more p until end matches, consume end and return what p matches
+    ///
+    /// end is tried before p each time. Fail if p fails before end matches.
+    fn many_till<T2, P2>(self, end: P2) -> ManyTill<T, Self, T2, P2>
+    where
+        Self: Sized,
+        P2: Parser<'a, T2>,
+    {
+        ManyTill(self, end, PhantomData)
+    }
+
+    /// match 0 or more p, separated by separator, stop on separator and p not consuming input like many
     fn separated_by<T2, P2>(self, separator: P2) -> SeparatedBy<T, Self, T2, P2>
     where
         Self: Sized,
-        P2: Parser<T2>,
+        P2: Parser<'a, T2>,
     {
         SeparatedBy(self, separator, PhantomData)
     }
 
-    /// try to match p, if success, consumes input and return (); otherwise returns () and does not consume input
+    /// try to match p, if success, consumes input and return Some(what p matches); otherwise returns None and does not
+    /// consume input
     fn optional(self) -> Optional<T, Self>
     where
         Self: Sized,
@@ -134,11 +343,38 @@ pub trait Parser<T> {
         Optional(self, PhantomData)
     }
 
-    /// match 0 or more p, separated by separator and optionally ended by separator
+    /// try to match p, if success, consumes input and return (); otherwise returns () and does not consume input
+    fn skip_optional(self) -> SkipOptional<T, Self>
+    where
+        Self: Sized,
+    {
+        SkipOptional(self, PhantomData)
+    }
+
+    /// try to match p, if fail, return T::default() and does not consume input
+    fn or_default(self) -> OrDefault<T, Self>
+    where
+        Self: Sized,
+        T: Default,
+    {
+        OrDefault(self, PhantomData)
+    }
+
+    /// try to match p, if fail, return value and does not consume input
+    fn or(self, value: T) -> Or<T, Self>
+    where
+        Self: Sized,
+        T: Clone,
+    {
+        Or(self, value)
+    }
+
+    /// match 0 or more p, separated by separator and optionally ended by separator, stop on separator and p not
+    /// consuming input like many
     fn separated_end_by<T2, P2>(self, separator: P2) -> SeparatedEndBy<T, Self, T2, P2>
     where
         Self: Sized,
-        P2: Parser<T2>,
+        P2: Parser<'a, T2>,
     {
         SeparatedEndBy(self, separator, PhantomData)
     }
@@ -147,7 +383,7 @@ pub trait Parser<T> {
     fn end_by<T2, P2>(self, separator: P2) -> Many<Left<T, Self, T2, P2>>
     where
         Self: Sized,
-        P2: Parser<T2>,
+        P2: Parser<'a, T2>,
     {
         self.left(separator).many()
     }
@@ -155,7 +391,7 @@ pub trait Parser<T> {
     fn end_by1<T2, P2>(self, separator: P2) -> Many1<Left<T, Self, T2, P2>>
     where
         Self: Sized,
-        P2: Parser<T2>,
+        P2: Parser<'a, T2>,
     {
         self.left(separator).many1()
     }
@@ -163,7 +399,7 @@ pub trait Parser<T> {
     fn chain_left1<T2, P2>(self, operator: P2) -> ChainLeft1<T, Self, T2, P2>
     where
         Self: Sized,
-        P2: Parser<T2>,
+        P2: Parser<'a, T2>,
     {
         ChainLeft1(self, operator, PhantomData)
     }
@@ -171,22 +407,46 @@ pub trait Parser<T> {
     fn chain_right1<T2, P2>(self, operator: P2) -> ChainRight1<T, Self, T2, P2>
     where
         Self: Sized,
-        P2: Parser<T2>,
+        P2: Parser<'a, T2>,
     {
         ChainRight1(self, operator, PhantomData)
     }
+
+    /// match p, but fail instead of going deeper when already nested inside depth max_depth guards
+    ///
+    /// put it inside recursive rules so that hostile input like "((((...))))" fails instead of overflowing the stack.
+    /// All guards on the same thread share one counter.
+    fn max_depth(self, depth: usize) -> MaxDepth<Self>
+    where
+        Self: Sized,
+    {
+        MaxDepth(self, depth)
+    }
+
+    /// log when p starts, succeeds or fails, with where and what it consumed, indented by nesting
+    ///
+    /// logs to stderr after set_trace(true), or to the sink of parse_with_trace. Does nothing otherwise.
+    fn trace<'b, S>(self, name: S) -> Trace<'b, Self>
+    where
+        Self: Sized,
+        S: Into<Cow<'b, str>>,
+    {
+        Trace(self, name.into())
+    }
 }
 
 #[derive(Clone)]
 pub struct Any;
 
-impl Parser<char> for Any {
-    fn parse<'a>(&self, input: &'a str) -> Option<(char, &'a str)> {
-        if let Some(first) = input.chars().next() {
-            Some((first.clone(), &input[first.len_utf8()..]))
-        } else {
-            None
-        }
+impl<'a> Parser<'a, char> for Any {
+    fn parse(&self, input: &'a str) -> Option<(char, &'a str)> {
+        budget::primitive(|| {
+            if let Some(first) = input.chars().next() {
+                Some((first, &input[first.len_utf8()..]))
+            } else {
+                None
+            }
+        })
     }
 }
 
@@ -202,13 +462,15 @@ pub fn any(s: &str) -> Option<(char, &str)> {
 #[derive(Clone)]
 pub struct Eof;
 
-impl Parser<()> for Eof {
-    fn parse<'a>(&self, input: &'a str) -> Option<((), &'a str)> {
-        if input.is_empty() {
-            Some(((), input))
-        } else {
-            None
-        }
+impl<'a> Parser<'a, ()> for Eof {
+    fn parse(&self, input: &'a str) -> Option<((), &'a str)> {
+        budget::primitive(|| {
+            if input.is_empty() {
+                Some(((), input))
+            } else {
+                None
+            }
+        })
     }
 }
 
@@ -220,9 +482,9 @@ pub fn eof(s: &str) -> Option<((), &str)> {
 #[derive(Clone)]
 pub struct Epsilon;
 
-impl Parser<()> for Epsilon {
-    fn parse<'a>(&self, input: &'a str) -> Option<((), &'a str)> {
-        Some(((), input))
+impl<'a> Parser<'a, ()> for Epsilon {
+    fn parse(&self, input: &'a str) -> Option<((), &'a str)> {
+        budget::primitive(|| Some(((), input)))
     }
 }
 
@@ -235,11 +497,11 @@ pub fn epsilon(s: &str) -> Option<((), &str)> {
 // #[derive(Clone)]
 // pub struct Always<T>(T);
 
-// impl<T> Parser<T> for Always<T>
+// impl<'a, T> Parser<'a, T> for Always<T>
 // where
 //     T: Clone,
 // {
-//     fn parse<'a>(&self, input: &'a str) -> Option<(T, &'a str)> {
+//     fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
 //         Some((self.0.clone(), input))
 //     }
 // }
@@ -255,20 +517,24 @@ pub fn epsilon(s: &str) -> Option<((), &str)> {
 pub struct Satisfy<F>(F);
 // 不用担心如果F不满足Clone怎么办，根据文档，derive(Clone)其实相当于impl<F> Clone for Satisfy<F> where F: Clone，当且仅当F也满足Clone时才会让Satisfy<F>也满足Clone，非常贴心
 
-impl<F> Parser<char> for Satisfy<F>
+impl<'a, F> Parser<'a, char> for Satisfy<F>
 where
     F: Fn(char) -> bool, // Fn(char) -> bool
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(char, &'a str)> {
-        if let Some(first) = input.chars().next() {
-            if (self.0)(first) {
-                Some((first.clone(), &input[first.len_utf8()..]))
+    fn parse(&self, input: &'a str) -> Option<(char, &'a str)> {
+        budget::primitive(|| {
+            if let Some(first) = input.chars().next() {
+                if (self.0)(first) {
+                    let remaining = &input[first.len_utf8()..];
+                    cst::token(|| cst::SATISFY.into(), input, remaining);
+                    Some((first, remaining))
+                } else {
+                    None
+                }
             } else {
                 None
             }
-        } else {
-            None
-        }
+        })
     }
 }
 
@@ -283,17 +549,22 @@ where
 #[derive(Clone)]
 pub struct Char(char);
 
-impl Parser<char> for Char {
-    fn parse<'a>(&self, input: &'a str) -> Option<(char, &'a str)> {
-        if let Some(first) = input.chars().next() {
-            if first == self.0 {
-                Some((first.clone(), &input[first.len_utf8()..]))
+impl<'a> Parser<'a, char> for Char {
+    fn parse(&self, input: &'a str) -> Option<(char, &'a str)> {
+        budget::primitive(|| {
+            if let Some(first) = input.chars().next() {
+                if first == self.0 {
+                    let remaining = &input[first.len_utf8()..];
+                    cst::token(|| first.to_string().into(), input, remaining);
+                    Some((first, remaining))
+                } else {
+                    None
+                }
             } else {
+                complete::expect(|| self.0.to_string());
                 None
             }
-        } else {
-            None
-        }
+        })
     }
 }
 
@@ -311,9 +582,13 @@ pub fn whitespace(s: &str) -> Option<(char, &str)> {
 #[derive(Clone)]
 pub struct Whitespaces;
 
-impl Parser<()> for Whitespaces {
-    fn parse<'a>(&self, input: &'a str) -> Option<((), &'a str)> {
-        Some(((), input.trim_start()))
+impl<'a> Parser<'a, ()> for Whitespaces {
+    fn parse(&self, input: &'a str) -> Option<((), &'a str)> {
+        budget::primitive(|| {
+            let remaining = input.trim_start();
+            cst::token(|| cst::WHITESPACE.into(), input, remaining);
+            Some(((), remaining))
+        })
     }
 }
 
@@ -354,71 +629,188 @@ pub fn alphanumeric(s: &str) -> Option<(char, &str)> {
 
 /// '0'..='9'
 pub fn digit(s: &str) -> Option<(char, &str)> {
-    satisfy(|c| c.is_digit(10)).parse(s)
+    satisfy(|c| c.is_ascii_digit()).token(cst::DIGIT).parse(s)
 }
 
 /// '0'..='9', 'a'..='f' and 'A'..='F'
 pub fn hex_digit(s: &str) -> Option<(char, &str)> {
-    satisfy(|c| c.is_digit(16)).parse(s)
+    satisfy(|c| c.is_ascii_hexdigit()).parse(s)
 }
 
 /// 1 character that is an element of the char slice
-pub fn one_of<'a>(array: &'a [char]) -> impl Parser<char> + Clone + 'a {
+pub fn one_of(array: &[char]) -> impl for<'a> Parser<'a, char> + Clone + '_ {
     satisfy(move |c| array.contains(&c)) // 一定要move，array需要移动到closure里面
 }
 
 /// 1 character that is not an element of the char slice
-pub fn none_of<'a>(array: &'a [char]) -> impl Parser<char> + Clone + 'a {
+pub fn none_of(array: &[char]) -> impl for<'a> Parser<'a, char> + Clone + '_ {
     satisfy(move |c| !array.contains(&c))
 }
 
 #[derive(Clone)]
-pub struct Str<'a>(&'a str);
+pub struct TakeWhile<F>(F, usize, usize);
+
+impl<'a, F> Parser<'a, &'a str> for TakeWhile<F>
+where
+    F: Fn(char) -> bool,
+{
+    fn parse(&self, input: &'a str) -> Option<(&'a str, &'a str)> {
+        budget::primitive(|| {
+            let mut count = 0;
+            let mut end = input.len();
+
+            for (i, c) in input.char_indices() {
+                if count == self.2 || !(self.0)(c) {
+                    end = i;
+                    break;
+                }
+                count += 1;
+            }
+
+            if count < self.1 {
+                None
+            } else {
+                let (a, remaining) = input.split_at(end);
+                cst::token(|| cst::TAKE_WHILE.into(), input, remaining);
+                Some((a, remaining))
+            }
+        })
+    }
+}
+
+/// 0 or more characters c that make f(c) true, return the slice of input without allocation
+pub fn take_while<F>(f: F) -> TakeWhile<F>
+where
+    F: Fn(char) -> bool,
+{
+    TakeWhile(f, 0, usize::MAX)
+}
+
+/// 1 or more characters c that make f(c) true
+pub fn take_while1<F>(f: F) -> TakeWhile<F>
+where
+    F: Fn(char) -> bool,
+{
+    TakeWhile(f, 1, usize::MAX)
+}
+
+/// m to n (inclusive) characters c that make f(c) true, stop after n characters
+pub fn take_while_m_n<F>(m: usize, n: usize, f: F) -> TakeWhile<F>
+where
+    F: Fn(char) -> bool,
+{
+    TakeWhile(f, m, n)
+}
+
+#[derive(Clone)]
+pub struct Str<'b>(Cow<'b, str>);
 // 为什么这里不用pub呢？
+// 用Cow是为了运行时拼出来的关键字表也能用，比如string(format!("{}:", key))
+
+impl<'a, 'b> Parser<'a, &'a str> for Str<'b> {
+    fn parse(&self, input: &'a str) -> Option<(&'a str, &'a str)> {
+        budget::primitive(|| {
+            // 以前是一个一个char比较，然后和self.0.len()比，可是len()是字节数，遇到非ASCII字符就永远匹配不上了
+            if input.starts_with(self.0.as_ref()) {
+                let (a, remaining) = input.split_at(self.0.len());
+                cst::token(|| self.0.to_string().into(), input, remaining);
+                Some((a, remaining))
+            } else {
+                // 输入在pattern的中间结束了，补全的时候提示整个pattern
+                if self.0.len() > input.len() && self.0.starts_with(input) {
+                    complete::expect(|| self.0.to_string());
+                }
+                None
+            }
+        })
+    }
+}
 
-impl<'b> Parser<&'b str> for Str<'b> {
-    fn parse<'a>(&self, input: &'a str) -> Option<(&'b str, &'a str)> {
-        if input
-            .chars()
-            .zip(self.0.chars())
-            .filter(|(v, w)| v == w)
-            .count()
-            == self.0.len()
-        {
-            Some((self.0, &input[self.0.len()..]))
-        } else {
-            None
-        }
+/// match a particular string, return the matched slice of input
+pub fn string<'b, S>(pattern: S) -> Str<'b>
+where
+    S: Into<Cow<'b, str>>,
+{
+    Str(pattern.into())
+}
+
+#[derive(Clone)]
+pub struct StrNoCase<'b>(Cow<'b, str>);
+
+impl<'a, 'b> Parser<'a, &'a str> for StrNoCase<'b> {
+    fn parse(&self, input: &'a str) -> Option<(&'a str, &'a str)> {
+        budget::primitive(|| {
+            let mut chars = input.char_indices();
+            let mut end = 0;
+
+            for w in self.0.chars() {
+                let (i, v) = match chars.next() {
+                    Some(v) => v,
+                    None => {
+                        complete::expect(|| self.0.to_string());
+                        return None;
+                    }
+                };
+                // 只比较小写的话，σ和ς就不相等了，所以大写也比较一下
+                if v != w
+                    && !v.to_lowercase().eq(w.to_lowercase())
+                    && !v.to_uppercase().eq(w.to_uppercase())
+                {
+                    return None;
+                }
+                end = i + v.len_utf8();
+            }
+
+            Some(input.split_at(end))
+        })
     }
 }
 
-/// match a particular string
-pub fn string<'a>(pattern: &'a str) -> Str<'a> {
-    Str(pattern)
+/// match a particular string ignoring case, return the matched slice of input
+pub fn string_no_case<'b, S>(pattern: S) -> StrNoCase<'b>
+where
+    S: Into<Cow<'b, str>>,
+{
+    StrNoCase(pattern.into())
+}
+
+// 内层parser成功了却一点输入都没吃，再循环下去就是死循环。debug的时候直接panic，方便找到是哪个parser写错了；release的时候就停下来
+pub(crate) fn no_progress(combinator: &str) {
+    if cfg!(debug_assertions) {
+        panic!(
+            "parser inside {} succeeded without consuming input, it would loop forever",
+            combinator
+        );
+    }
 }
 
 #[derive(Clone)]
 pub struct Many<P>(P);
 
-// impl<P, T> Parser<&[T]> for Many<P> where P: Parser<T> {
-//     fn parse<'a>(&self, input: &'a str) -> Option<(&[T], &'a str)> {
+// impl<'a, P, T> Parser<'a, &[T]> for Many<P> where P: Parser<T> {
+//     fn parse(&self, input: &'a str) -> Option<(&[T], &'a str)> {
 
 //     }
 // }
 // 这应该是做不到的
 
-impl<T, P> Parser<Vec<T>> for Many<P>
+impl<'a, T, P> Parser<'a, Vec<T>> for Many<P>
 where
-    P: Parser<T>,
+    P: Parser<'a, T>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(Vec<T>, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(Vec<T>, &'a str)> {
         let mut input = input;
         let mut target = vec![];
 
         loop {
-            if let Some((a, remaining)) = self.0.parse(input) {
+            if let Some((a, remaining)) = state::attempt(|| self.0.parse(input)) {
+                let stuck = remaining.len() == input.len();
                 input = remaining;
                 target.push(a);
+                if stuck {
+                    no_progress("Many");
+                    break Some((target, input));
+                }
             } else {
                 break Some((target, input));
             }
@@ -426,20 +818,26 @@ where
     }
 }
 
-impl<P> Parser<String> for Many<P>
+impl<'a, P> Parser<'a, String> for Many<P>
 // 不知道怎么改成Parser<&str>呜呜呜
 // 我错了，应该是做不到的
+// 又错了，Parser<'a, T>之后就可以了，不想分配内存的话用recognize(p.many())或者take_while
 where
-    P: Parser<char>,
+    P: Parser<'a, char>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(String, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(String, &'a str)> {
         let mut input = input;
         let mut target = String::new();
 
         loop {
-            if let Some((c, remaining)) = self.0.parse(input) {
+            if let Some((c, remaining)) = state::attempt(|| self.0.parse(input)) {
+                let stuck = remaining.len() == input.len();
                 input = remaining;
                 target.push(c);
+                if stuck {
+                    no_progress("Many");
+                    break Some((target, input));
+                }
             } else {
                 break Some((target, input));
             }
@@ -451,11 +849,11 @@ where
 // 这就带来一个问题，假设p: Parser<char>，那么p.many().parse的类型应该是Parser<Vec<char>>还是Parser<String>呢？
 // 所以有时候会出现需要type annotation的情况。
 
-// impl<P> Parser<String> for P
+// impl<'a, P> Parser<'a, String> for P
 // where
 //     P: Parser<Vec<char>>,
 // {
-//     fn parse<'a>(&self, input: &'a str) -> Option<(String, &'a str)> {
+//     fn parse(&self, input: &'a str) -> Option<(String, &'a str)> {
 //         if let Some((s, remaining)) = self.parse(input) {
 //             Some((s.into_iter().collect(), remaining))
 //         } else {
@@ -468,18 +866,23 @@ where
 #[derive(Clone)]
 pub struct Many1<P>(P);
 
-impl<T, P> Parser<Vec<T>> for Many1<P>
+impl<'a, T, P> Parser<'a, Vec<T>> for Many1<P>
 where
-    P: Parser<T>,
+    P: Parser<'a, T>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(Vec<T>, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(Vec<T>, &'a str)> {
         let mut input = input;
         let mut target = vec![];
 
         loop {
-            if let Some((a, remaining)) = self.0.parse(input) {
+            if let Some((a, remaining)) = state::attempt(|| self.0.parse(input)) {
+                let stuck = remaining.len() == input.len();
                 input = remaining;
                 target.push(a);
+                if stuck {
+                    no_progress("Many1");
+                    break Some((target, input));
+                }
             } else {
                 break if target.is_empty() {
                     None
@@ -491,18 +894,23 @@ where
     }
 }
 
-impl<P> Parser<String> for Many1<P>
+impl<'a, P> Parser<'a, String> for Many1<P>
 where
-    P: Parser<char>,
+    P: Parser<'a, char>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(String, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(String, &'a str)> {
         let mut input = input;
         let mut target = String::new();
 
         loop {
-            if let Some((c, remaining)) = self.0.parse(input) {
+            if let Some((c, remaining)) = state::attempt(|| self.0.parse(input)) {
+                let stuck = remaining.len() == input.len();
                 input = remaining;
                 target.push(c);
+                if stuck {
+                    no_progress("Many1");
+                    break Some((target, input));
+                }
             } else {
                 break if target.is_empty() {
                     None
@@ -515,16 +923,146 @@ where
 }
 // 所有都要写两遍，代码还都差不多，好烦哦
 
+#[derive(Clone)]
+pub struct ManyTill<T1, P1, T2, P2>(P1, P2, PhantomData<(T1, T2)>);
+
+impl<'a, T1, P1, T2, P2> Parser<'a, Vec<T1>> for ManyTill<T1, P1, T2, P2>
+where
+    P1: Parser<'a, T1>,
+    P2: Parser<'a, T2>,
+{
+    fn parse(&self, input: &'a str) -> Option<(Vec<T1>, &'a str)> {
+        let mut input = input;
+        let mut target = vec![];
+
+        loop {
+            if let Some((_, remaining)) = state::attempt(|| self.1.parse(input)) {
+                break Some((target, remaining));
+            }
+
+            let (a, remaining) = self.0.parse(input)?;
+            if remaining.len() == input.len() {
+                // 不停下来的话end永远不会成功，只能当作失败
+                no_progress("ManyTill");
+                break None;
+            }
+            input = remaining;
+            target.push(a);
+        }
+    }
+}
+
+impl<'a, P1, T2, P2> Parser<'a, String> for ManyTill<char, P1, T2, P2>
+where
+    P1: Parser<'a, char>,
+    P2: Parser<'a, T2>,
+{
+    fn parse(&self, input: &'a str) -> Option<(String, &'a str)> {
+        let mut input = input;
+        let mut target = String::new();
+
+        loop {
+            if let Some((_, remaining)) = state::attempt(|| self.1.parse(input)) {
+                break Some((target, remaining));
+            }
+
+            let (c, remaining) = self.0.parse(input)?;
+            if remaining.len() == input.len() {
+                no_progress("ManyTill");
+                break None;
+            }
+            input = remaining;
+            target.push(c);
+        }
+    }
+}
+
+// 找到第一个能让p成功的位置，返回这个位置和p吃剩下的输入
+fn find<'a, T, P>(p: &P, input: &'a str) -> Option<(usize, &'a str)>
+where
+    P: Parser<'a, T>,
+{
+    input
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(input.len()))
+        .find_map(|i| state::attempt(|| p.parse(&input[i..])).map(|(_, remaining)| (i, remaining)))
+}
+
+#[derive(Clone)]
+pub struct SkipUntil<T, P>(P, PhantomData<T>);
+
+impl<'a, T, P> Parser<'a, ()> for SkipUntil<T, P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<((), &'a str)> {
+        let (_, remaining) = find(&self.0, input)?;
+        Some(((), remaining))
+    }
+}
+
+/// skip any characters until end matches, consume end too
+pub fn skip_until<'a, T, P>(end: P) -> SkipUntil<T, P>
+where
+    P: Parser<'a, T>,
+{
+    SkipUntil(end, PhantomData)
+}
+
+#[derive(Clone)]
+pub struct TakeUntil<T, P>(P, PhantomData<T>);
+
+impl<'a, T, P> Parser<'a, &'a str> for TakeUntil<T, P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<(&'a str, &'a str)> {
+        let (i, _) = find(&self.0, input)?;
+        Some((&input[..i], &input[i..]))
+    }
+}
+
+/// return the input before the first place pattern matches, without allocation. pattern itself is not consumed.
+pub fn take_until<'a, T, P>(pattern: P) -> TakeUntil<T, P>
+where
+    P: Parser<'a, T>,
+{
+    TakeUntil(pattern, PhantomData)
+}
+
+#[derive(Clone)]
+pub struct Recognize<T, P>(P, PhantomData<T>);
+
+impl<'a, T, P> Parser<'a, &'a str> for Recognize<T, P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<(&'a str, &'a str)> {
+        let (_, remaining) = self.0.parse(input)?;
+        // remaining一定是input的后缀，所以长度一减就知道吃掉了多少
+        Some((&input[..input.len() - remaining.len()], remaining))
+    }
+}
+
+/// match p, return the slice of input p consumes instead of what p matches
+pub fn recognize<'a, T, P>(p: P) -> Recognize<T, P>
+where
+    P: Parser<'a, T>,
+{
+    Recognize(p, PhantomData)
+}
+
 #[derive(Clone)]
 pub struct Choice<P1, P2>(P1, P2);
 
-impl<T, P1, P2> Parser<T> for Choice<P1, P2>
+impl<'a, T, P1, P2> Parser<'a, T> for Choice<P1, P2>
 where
-    P1: Parser<T>,
-    P2: Parser<T>,
+    P1: Parser<'a, T>,
+    P2: Parser<'a, T>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(T, &'a str)> {
-        if let Some((a, remaining)) = self.0.parse(input) {
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        if let Some((a, remaining)) = state::attempt(|| self.0.parse(input)) {
             Some((a, remaining))
         } else if let Some((b, remaining)) = self.1.parse(input) {
             Some((b, remaining))
@@ -560,12 +1098,12 @@ pub struct Map<T, P, F>(P, F, PhantomData<T>);
 // https://stackoverflow.com/questions/28123445/is-there-any-way-to-work-around-an-unused-type-parameter
 // https://github.com/rust-lang/rust/issues/23246
 
-impl<T1, P1, T2, F> Parser<T2> for Map<T1, P1, F>
+impl<'a, T1, P1, T2, F> Parser<'a, T2> for Map<T1, P1, F>
 where
-    P1: Parser<T1>,
+    P1: Parser<'a, T1>,
     F: Fn(T1) -> T2,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(T2, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(T2, &'a str)> {
         if let Some((res, remaining)) = self.0.parse(input) {
             Some(((self.1)(res), remaining))
         } else {
@@ -589,13 +1127,13 @@ where
 #[derive(Clone)]
 pub struct AndThen<T, P, F>(P, F, PhantomData<T>);
 
-impl<T1, P1, T2, P2, F> Parser<T2> for AndThen<T1, P1, F>
+impl<'a, T1, P1, T2, P2, F> Parser<'a, T2> for AndThen<T1, P1, F>
 where
-    P1: Parser<T1>,
-    P2: Parser<T2>,
+    P1: Parser<'a, T1>,
+    P2: Parser<'a, T2>,
     F: Fn(T1) -> P2,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(T2, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(T2, &'a str)> {
         if let Some((res, remaining)) = self.0.parse(input) {
             (self.1)(res).parse(remaining)
         } else {
@@ -607,11 +1145,11 @@ where
 #[derive(Clone)]
 pub struct Count<P>(P, usize);
 
-impl<T, P> Parser<Vec<T>> for Count<P>
+impl<'a, T, P> Parser<'a, Vec<T>> for Count<P>
 where
-    P: Parser<T>,
+    P: Parser<'a, T>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(Vec<T>, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(Vec<T>, &'a str)> {
         let mut input = input;
         let mut res = vec![];
 
@@ -628,11 +1166,11 @@ where
     }
 }
 
-impl<P> Parser<String> for Count<P>
+impl<'a, P> Parser<'a, String> for Count<P>
 where
-    P: Parser<char>,
+    P: Parser<'a, char>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(String, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(String, &'a str)> {
         let mut input = input;
         let mut res = String::new();
 
@@ -649,15 +1187,82 @@ where
     }
 }
 
+#[derive(Clone)]
+pub struct Repeat<P>(P, usize, usize);
+
+impl<'a, T, P> Parser<'a, Vec<T>> for Repeat<P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<(Vec<T>, &'a str)> {
+        let mut input = input;
+        let mut res = vec![];
+
+        while res.len() < self.2 {
+            if let Some((a, remaining)) = state::attempt(|| self.0.parse(input)) {
+                let stuck = remaining.len() == input.len();
+                input = remaining;
+                res.push(a);
+                // 不吃输入的话再重复也是一样的结果，凑够下限以后还要再转就是死循环，不管上限有多大
+                if stuck && res.len() > self.1 && res.len() < self.2 {
+                    no_progress("Repeat");
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+
+        if res.len() < self.1 {
+            None
+        } else {
+            Some((res, input))
+        }
+    }
+}
+
+impl<'a, P> Parser<'a, String> for Repeat<P>
+where
+    P: Parser<'a, char>,
+{
+    fn parse(&self, input: &'a str) -> Option<(String, &'a str)> {
+        let mut input = input;
+        let mut res = String::new();
+        let mut n = 0;
+
+        while n < self.2 {
+            if let Some((c, remaining)) = state::attempt(|| self.0.parse(input)) {
+                let stuck = remaining.len() == input.len();
+                input = remaining;
+                res.push(c);
+                n += 1;
+                // 不吃输入的话再重复也是一样的结果，凑够下限以后还要再转就是死循环，不管上限有多大
+                if stuck && n > self.1 && n < self.2 {
+                    no_progress("Repeat");
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+
+        if n < self.1 {
+            None
+        } else {
+            Some((res, input))
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Left<T1, P1, T2, P2>(P1, P2, PhantomData<(T1, T2)>);
 
-impl<T1, P1, T2, P2> Parser<T1> for Left<T1, P1, T2, P2>
+impl<'a, T1, P1, T2, P2> Parser<'a, T1> for Left<T1, P1, T2, P2>
 where
-    P1: Parser<T1>,
-    P2: Parser<T2>,
+    P1: Parser<'a, T1>,
+    P2: Parser<'a, T2>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(T1, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(T1, &'a str)> {
         if let Some((a, remaining)) = self.0.parse(input) {
             if let Some((_, remaining)) = self.1.parse(remaining) {
                 Some((a, remaining))
@@ -673,12 +1278,12 @@ where
 #[derive(Clone)]
 pub struct Right<T1, P1, T2, P2>(P1, P2, PhantomData<(T1, T2)>);
 
-impl<T1, P1, T2, P2> Parser<T2> for Right<T1, P1, T2, P2>
+impl<'a, T1, P1, T2, P2> Parser<'a, T2> for Right<T1, P1, T2, P2>
 where
-    P1: Parser<T1>,
-    P2: Parser<T2>,
+    P1: Parser<'a, T1>,
+    P2: Parser<'a, T2>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(T2, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(T2, &'a str)> {
         if let Some((_, remaining)) = self.0.parse(input) {
             if let Some((b, remaining)) = self.1.parse(remaining) {
                 Some((b, remaining))
@@ -691,20 +1296,20 @@ where
     }
 }
 
-// impl<T> Parser<T> for Box<dyn Parser<T>> {
-//     fn parse<'a>(&self, input: &'a str) -> Option<(T, &'a str)> {
+// impl<'a, T> Parser<'a, T> for Box<dyn Parser<T>> {
+//     fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
 //         self.parse(input)
 //     }
 // }
 
-// impl<T> Parser<T> for std::rc::Rc<dyn Parser<T>> {
-//     fn parse<'a>(&self, input: &'a str) -> Option<(T, &'a str)> {
+// impl<'a, T> Parser<'a, T> for std::rc::Rc<dyn Parser<T>> {
+//     fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
 //         self.parse(input)
 //     }
 // }
 
-// impl<T> Parser<T> for std::sync::Arc<dyn Parser<T>> {
-//     fn parse<'a>(&self, input: &'a str) -> Option<(T, &'a str)> {
+// impl<'a, T> Parser<'a, T> for std::sync::Arc<dyn Parser<T>> {
+//     fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
 //         self.parse(input)
 //     }
 // }
@@ -713,12 +1318,12 @@ where
 #[derive(Clone)]
 pub struct Function<F>(F);
 
-impl<T, F> Parser<T> for Function<F>
+impl<'a, T, F> Parser<'a, T> for Function<F>
 where
     // F: for<'r> Fn(&'r str) -> Option<(T, &'r str)>, // 这个for<'r>是什么意思？
-    F: Fn(&str) -> Option<(T, &str)>,
+    F: Fn(&'a str) -> Option<(T, &'a str)>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(T, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
         (self.0)(input)
     }
 }
@@ -733,11 +1338,11 @@ where
 }
 
 // 梦想终于实现了！
-impl<T, F> Parser<T> for F
+impl<'a, T, F> Parser<'a, T> for F
 where
-    F: Fn(&str) -> Option<(T, &str)>,
+    F: Fn(&'a str) -> Option<(T, &'a str)>, // 这样T里面也可以借用input了
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(T, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
         (self)(input)
     }
 }
@@ -748,12 +1353,13 @@ where
 #[derive(Clone)]
 pub struct LookAhead<P>(P);
 
-impl<T, P> Parser<T> for LookAhead<P>
+impl<'a, T, P> Parser<'a, T> for LookAhead<P>
 where
-    P: Parser<T>,
+    P: Parser<'a, T>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(T, &'a str)> {
-        if let Some((a, _)) = self.0.parse(input) {
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        // 不吃输入，对状态的改动也不留下
+        if let Some((a, _)) = state::peek(|| self.0.parse(input)) {
             Some((a, input))
         } else {
             None
@@ -761,23 +1367,59 @@ where
     }
 }
 
-pub fn symbol(s: &str) -> impl Parser<&str> + Clone {
-    Str(s).left(whitespaces)
+/// match p and return what it matches, consume no input even if success
+pub fn peek<'a, T, P>(p: P) -> LookAhead<P>
+where
+    P: Parser<'a, T>,
+{
+    LookAhead(p)
+}
+
+#[derive(Clone)]
+pub struct Not<T, P>(P, PhantomData<T>);
+
+impl<'a, T, P> Parser<'a, ()> for Not<T, P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<((), &'a str)> {
+        // 和look_ahead反过来，p失败的时候才成功，同样不吃输入
+        match state::peek(|| self.0.parse(input)) {
+            Some(_) => None,
+            None => Some(((), input)),
+        }
+    }
+}
+
+/// succeed without consuming input if p fails, fail if p succeeds
+pub fn not<'a, T, P>(p: P) -> Not<T, P>
+where
+    P: Parser<'a, T>,
+{
+    Not(p, PhantomData)
+}
+
+pub fn symbol<'a, 'b, S>(s: S) -> Left<&'a str, Str<'b>, (), Whitespaces>
+where
+    S: Into<Cow<'b, str>>,
+{
+    string(s).lexeme()
 }
 // 觉得这个好像没什么用orz
 
 // 不限定parse出来是u64或者其他类型，方便和无限位精度库梦幻联动
+/// 1 or more digits parsed into T, fail if T can't hold it
 pub fn decimal<T, E>(input: &str) -> Option<(T, &str)>
 where
     T: FromStr<Err = E>,
     E: Debug, // 这好烦
 {
-    digit
-        .many1()
-        .map(|v: String| v.parse().unwrap())
-        .parse(input) // ...many1()之后无法确定是Parser<String>还是Parser<Vec<T>>，可以用map强行让编译器推断出前面是Parser<String>
+    // 以前是.parse().unwrap()，超出范围就直接panic了
+    let (digits, input) = take_while1(|c| c.is_ascii_digit()).parse(input)?;
+    Some((digits.parse().ok()?, input))
 }
 // 比如rug的无限精度Integer也实现了FromStr，所以可以直接parse出这个
+// 只想要基本类型的话，用checked_decimal更快，也不会分配内存
 
 pub fn sign(s: &str) -> Option<(char, &str)> {
     Choice(Char('+'), Char('-')).parse(s)
@@ -788,31 +1430,284 @@ where
     T: FromStr<Err = E>,
     E: Debug,
 {
-    let (sign_, input) = sign.left(whitespaces).parse(input).unwrap_or(('+', input));
-    let (digits, input) = digit.many1().map(|v: String| v).parse(input)?;
+    // 符号和数字中间可能有空格，整个算一个token
+    integer_literal::<T, E>.token(cst::INTEGER).parse(input)
+}
+
+fn integer_literal<T, E>(input: &str) -> Option<(T, &str)>
+where
+    T: FromStr<Err = E>,
+    E: Debug,
+{
+    let digits = take_while1(|c| c.is_ascii_digit());
+
+    // 符号和数字挨在一起的时候直接parse输入的切片，只有中间有空格的时候才需要拼一个String出来
+    if let Some((literal, input)) =
+        recognize(sign.skip_optional().right(digits.clone())).parse(input)
+    {
+        return Some((literal.parse().ok()?, input));
+    }
+
+    let (sign_, input) = sign.left(whitespaces).parse(input)?;
+    let (digits, input) = digits.parse(input)?;
     if let Ok(v) = format!("{}{}", sign_, digits).parse::<T>() {
         Some((v, input))
     } else {
         None
     }
 }
-// Haskell parsec的integer是lexeme的，而且可以parse十六进制
+// Haskell parsec的integer是lexeme的，而且可以parse十六进制
+
+/// primitive integer types that checked_decimal and checked_integer can parse into
+pub trait Integer: Copy {
+    fn zero() -> Self;
+
+    /// self * radix + digit, or self * radix - digit when negative, None on overflow
+    fn push_digit(self, radix: u32, digit: u32, negative: bool) -> Option<Self>;
+}
+
+macro_rules! impl_integer {
+    ($($t:ty),*) => {
+        $(
+            impl Integer for $t {
+                fn zero() -> Self {
+                    0
+                }
+
+                fn push_digit(self, radix: u32, digit: u32, negative: bool) -> Option<Self> {
+                    // radix最大36，i8也放得下
+                    let v = self.checked_mul(radix as $t)?;
+                    if negative {
+                        v.checked_sub(digit as $t)
+                    } else {
+                        v.checked_add(digit as $t)
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+#[derive(Clone)]
+pub struct CheckedInteger<T> {
+    signed: bool,
+    radix: u32, // 没有前缀的时候用这个进制，0表示一定要有前缀
+    prefixes: &'static [(&'static str, u32)], // 前缀和对应的进制，不区分大小写
+    based: bool, // Ada那样的16#FF#
+    separator: Option<char>,
+    phantom: PhantomData<T>,
+}
+
+impl<T> CheckedInteger<T> {
+    fn new(signed: bool, radix: u32, prefixes: &'static [(&'static str, u32)]) -> Self {
+        Self {
+            signed,
+            radix,
+            prefixes,
+            based: false,
+            separator: None,
+            phantom: PhantomData,
+        }
+    }
+
+    /// allow separator between digits, like 1_000_000
+    pub fn separator(self, separator: char) -> Self {
+        Self {
+            separator: Some(separator),
+            ..self
+        }
+    }
+
+    // 16#FF#里的16，和前缀一样直接看字符串，不再走别的parser，补全和预算都只算整个字面量
+    fn base<'a>(&self, input: &'a str) -> Option<(u32, &'a str)> {
+        let end = input
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(input.len());
+        let base = input[..end].parse::<u32>().ok()?;
+        let remaining = input[end..].strip_prefix('#')?;
+        if (2..=16).contains(&base) {
+            Some((base, remaining))
+        } else {
+            None
+        }
+    }
+
+    fn digits<'a>(&self, radix: u32, negative: bool, input: &'a str) -> Option<(T, &'a str)>
+    where
+        T: Integer,
+    {
+        // 负数直接往负方向累加，不然i8::MIN这种就放不下了
+        let mut acc = T::zero();
+        let mut chars = input.char_indices().peekable();
+        let mut end = 0;
+
+        while let Some(&(i, c)) = chars.peek() {
+            if let Some(d) = c.to_digit(radix) {
+                acc = acc.push_digit(radix, d, negative)?;
+                end = i + c.len_utf8();
+                chars.next();
+            } else if Some(c) == self.separator && end > 0 {
+                // 分隔符只能夹在两个数字中间
+                chars.next();
+                match chars.peek() {
+                    Some(&(_, c)) if c.is_digit(radix) => {}
+                    _ => break,
+                }
+            } else {
+                break;
+            }
+        }
+
+        if end == 0 {
+            None
+        } else {
+            Some((acc, &input[end..]))
+        }
+    }
+
+    fn literal<'a>(&self, input: &'a str) -> Option<(T, &'a str)>
+    where
+        T: Integer,
+    {
+        let (negative, input) = if !self.signed {
+            (false, input)
+        } else if let Some(remaining) = input.strip_prefix('-') {
+            (true, remaining)
+        } else {
+            (false, input.strip_prefix('+').unwrap_or(input))
+        };
+
+        // 前缀后面不是完整的字面量的话，就当没有前缀，按默认进制解析，比如0xg是0，16#FF是16。
+        // 溢出不算，直接失败
+        if self.based {
+            if let Some((base, remaining)) = self.base(input) {
+                if remaining.starts_with(|c: char| c.is_digit(base)) {
+                    let (v, remaining) = self.digits(base, negative, remaining)?;
+                    if let Some(remaining) = remaining.strip_prefix('#') {
+                        return Some((v, remaining));
+                    }
+                }
+            }
+        }
+
+        for (prefix, radix) in self.prefixes.iter() {
+            let remaining = input
+                .get(..prefix.len())
+                .filter(|v| v.eq_ignore_ascii_case(prefix))
+                .map(|_| &input[prefix.len()..]);
+            if let Some(remaining) = remaining {
+                if remaining.starts_with(|c: char| c.is_digit(*radix)) {
+                    return self.digits(*radix, negative, remaining);
+                }
+            }
+        }
+
+        if self.radix == 0 {
+            None
+        } else {
+            self.digits(self.radix, negative, input)
+        }
+    }
+}
+
+impl<'a, T> Parser<'a, T> for CheckedInteger<T>
+where
+    T: Integer,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        budget::primitive(|| {
+            let (v, remaining) = self.literal(input)?;
+            cst::token(|| cst::INTEGER.into(), input, remaining);
+            Some((v, remaining))
+        })
+    }
+}
+
+/// 1 or more digits parsed into a primitive integer without allocation, fail instead of panic on overflow
+pub fn checked_decimal<T>() -> CheckedInteger<T>
+where
+    T: Integer,
+{
+    CheckedInteger::new(false, 10, &[])
+}
+
+/// like checked_decimal, but with an optional + or - right before the digits
+pub fn checked_integer<T>() -> CheckedInteger<T>
+where
+    T: Integer,
+{
+    CheckedInteger::new(true, 10, &[])
+}
+
+/// 1 or more digits in radix (2 to 36, letters ignoring case), without any prefix
+///
+/// # Panics
+///
+/// panic if radix is not in 2..=36.
+pub fn radix_integer<T>(radix: u32) -> CheckedInteger<T>
+where
+    T: Integer,
+{
+    assert!((2..=36).contains(&radix), "radix must be in 2..=36");
+    CheckedInteger::new(false, radix, &[])
+}
+
+/// 0x or 0X, then hexadecimal digits
+pub fn hex_integer<T>() -> CheckedInteger<T>
+where
+    T: Integer,
+{
+    CheckedInteger::new(false, 0, &[("0x", 16)])
+}
+
+/// 0o or 0O, then octal digits
+pub fn oct_integer<T>() -> CheckedInteger<T>
+where
+    T: Integer,
+{
+    CheckedInteger::new(false, 0, &[("0o", 8)])
+}
+
+/// 0b or 0B, then binary digits
+pub fn bin_integer<T>() -> CheckedInteger<T>
+where
+    T: Integer,
+{
+    CheckedInteger::new(false, 0, &[("0b", 2)])
+}
+
+/// an optional sign, then a hexadecimal, octal, binary or decimal integer depending on its prefix
+///
+/// also accepts Ada style based literals like 16#FF# and 2#1010#, with a base from 2 to 16. A prefix or base not
+/// followed by a complete literal is not one, so the decimal number before it is taken: 0xg is 0 and 16#FF is 16.
+/// Overflow always fails.
+pub fn int_literal<T>() -> CheckedInteger<T>
+where
+    T: Integer,
+{
+    CheckedInteger {
+        based: true,
+        ..CheckedInteger::new(true, 10, &[("0x", 16), ("0o", 8), ("0b", 2)])
+    }
+}
 
 #[derive(Clone)]
 pub struct SeparatedBy<T1, P1, T2, P2>(P1, P2, PhantomData<(T1, T2)>);
 
 // 写的太难看了……
-impl<T1, P1, T2, P2> Parser<Vec<T1>> for SeparatedBy<T1, P1, T2, P2>
+impl<'a, T1, P1, T2, P2> Parser<'a, Vec<T1>> for SeparatedBy<T1, P1, T2, P2>
 where
-    P1: Parser<T1>,
-    P2: Parser<T2>,
+    P1: Parser<'a, T1>,
+    P2: Parser<'a, T2>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(Vec<T1>, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(Vec<T1>, &'a str)> {
         let mut input = input;
         let mut res = vec![];
 
         // 先尝试parse第一个元素
-        if let Some((v, remaining)) = self.0.parse(input) {
+        if let Some((v, remaining)) = state::attempt(|| self.0.parse(input)) {
             res.push(v);
             input = remaining;
         } else {
@@ -821,18 +1716,22 @@ where
 
         loop {
             // 然后parse分隔符、元素、分隔符、元素……
-            if let Some((_, tail1)) = self.1.parse(input) {
-                // tail1是吃掉分隔符之后的输入
-                if let Some((v, tail2)) = self.0.parse(tail1) {
-                    // tail2是吃掉元素之后的输入
-                    // 一定要分隔符、元素都成功了，这块才算结束
-                    res.push(v);
-                    input = tail2
-                } else {
-                    break Some((res, input)); // 一旦不成功就把input回退到parse分隔符之前的样子
+            // 一定要分隔符、元素都成功了，这块才算结束，不然分隔符对状态的改动也要撤销
+            let pair = state::attempt(|| {
+                // tail1是吃掉分隔符之后的输入，tail2是吃掉元素之后的输入
+                let (_, tail1) = self.1.parse(input)?;
+                self.0.parse(tail1)
+            });
+            if let Some((v, tail2)) = pair {
+                let stuck = tail2.len() == input.len();
+                res.push(v);
+                input = tail2;
+                if stuck {
+                    no_progress("SeparatedBy");
+                    break Some((res, input));
                 }
             } else {
-                break Some((res, input));
+                break Some((res, input)); // 一旦不成功就把input回退到parse分隔符之前的样子
             }
         }
     }
@@ -841,12 +1740,29 @@ where
 #[derive(Clone)]
 pub struct Optional<T, P>(P, PhantomData<T>);
 
-impl<T, P> Parser<()> for Optional<T, P>
+impl<'a, T, P> Parser<'a, Option<T>> for Optional<T, P>
 where
-    P: Parser<T>,
+    P: Parser<'a, T>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<((), &'a str)> {
-        if let Some((_, remaining)) = self.0.parse(input) {
+    fn parse(&self, input: &'a str) -> Option<(Option<T>, &'a str)> {
+        if let Some((a, remaining)) = state::attempt(|| self.0.parse(input)) {
+            Some((Some(a), remaining))
+        } else {
+            Some((None, input))
+        }
+    }
+}
+
+// 以前的optional就是这样的，什么都不返回
+#[derive(Clone)]
+pub struct SkipOptional<T, P>(P, PhantomData<T>);
+
+impl<'a, T, P> Parser<'a, ()> for SkipOptional<T, P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<((), &'a str)> {
+        if let Some((_, remaining)) = state::attempt(|| self.0.parse(input)) {
             Some(((), remaining))
         } else {
             Some(((), input))
@@ -854,20 +1770,54 @@ where
     }
 }
 
+#[derive(Clone)]
+pub struct OrDefault<T, P>(P, PhantomData<T>);
+
+impl<'a, T, P> Parser<'a, T> for OrDefault<T, P>
+where
+    P: Parser<'a, T>,
+    T: Default,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        if let Some((a, remaining)) = state::attempt(|| self.0.parse(input)) {
+            Some((a, remaining))
+        } else {
+            Some((T::default(), input))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Or<T, P>(P, T);
+
+impl<'a, T, P> Parser<'a, T> for Or<T, P>
+where
+    P: Parser<'a, T>,
+    T: Clone,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        if let Some((a, remaining)) = state::attempt(|| self.0.parse(input)) {
+            Some((a, remaining))
+        } else {
+            Some((self.1.clone(), input))
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct SeparatedEndBy<T1, P1, T2, P2>(P1, P2, PhantomData<(T1, T2)>);
 
-impl<T1, P1, T2, P2> Parser<Vec<T1>> for SeparatedEndBy<T1, P1, T2, P2>
+impl<'a, T1, P1, T2, P2> Parser<'a, Vec<T1>> for SeparatedEndBy<T1, P1, T2, P2>
 where
-    P1: Parser<T1>,
-    P2: Parser<T2>,
+    P1: Parser<'a, T1>,
+    P2: Parser<'a, T2>,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(Vec<T1>, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(Vec<T1>, &'a str)> {
         // 为什么不能在内部临时建parser然后直接用呢？
         let mut input = input;
         let mut res = vec![];
 
-        if let Some((v, remaining)) = self.0.parse(input) {
+        if let Some((v, remaining)) = state::attempt(|| self.0.parse(input)) {
             res.push(v);
             input = remaining;
         } else {
@@ -875,10 +1825,16 @@ where
         }
 
         loop {
-            if let Some((_, tail1)) = self.1.parse(input) {
-                if let Some((v, tail2)) = self.0.parse(tail1) {
+            // 分隔符留下的改动要保留，所以分开撤销
+            if let Some((_, tail1)) = state::attempt(|| self.1.parse(input)) {
+                if let Some((v, tail2)) = state::attempt(|| self.0.parse(tail1)) {
+                    let stuck = tail2.len() == input.len();
                     res.push(v);
-                    input = tail2
+                    input = tail2;
+                    if stuck {
+                        no_progress("SeparatedEndBy");
+                        break Some((res, input));
+                    }
                 } else {
                     break Some((res, tail1)); // 和SeparatedBy只有一个单词的区别。分隔符parse成功但元素不成功，不需要把input回退到parse分隔符之前的样子
                 }
@@ -892,29 +1848,27 @@ where
 #[derive(Clone)]
 pub struct ChainLeft1<T1, P1, T2, P2>(P1, P2, PhantomData<(T1, T2)>);
 
-impl<T1, P1, T2, P2> Parser<T1> for ChainLeft1<T1, P1, T2, P2>
+impl<'a, T1, P1, T2, P2> Parser<'a, T1> for ChainLeft1<T1, P1, T2, P2>
 where
-    P1: Parser<T1>,
-    P2: Parser<T2>,
+    P1: Parser<'a, T1>,
+    P2: Parser<'a, T2>,
     T2: Fn(T1, T1) -> T1,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(T1, &'a str)> {
+    fn parse(&self, input: &'a str) -> Option<(T1, &'a str)> {
         if let Some((acc, remaining)) = self.0.parse(input) {
             let mut acc = acc;
             let mut input = remaining;
 
-            loop {
-                if let Some((f, tail1)) = self.1.parse(input) {
-                    if let Some((w, tail2)) = self.0.parse(tail1) {
-                        input = tail2;
-                        acc = f(acc, w);
-                    } else {
-                        break Some((acc, input));
-                    }
-                } else {
-                    break Some((acc, input));
-                }
+            // 运算符成功、操作数失败的时候运算符对状态的改动也要撤销
+            while let Some((f, w, tail2)) = state::attempt(|| {
+                let (f, tail1) = self.1.parse(input)?;
+                let (w, tail2) = self.0.parse(tail1)?;
+                Some((f, w, tail2))
+            }) {
+                input = tail2;
+                acc = f(acc, w);
             }
+            Some((acc, input))
         } else {
             None
         }
@@ -924,30 +1878,71 @@ where
 #[derive(Clone)]
 pub struct ChainRight1<T1, P1, T2, P2>(P1, P2, PhantomData<(T1, T2)>);
 
-impl<T1, P1, T2, P2> Parser<T1> for ChainRight1<T1, P1, T2, P2>
+impl<'a, T1, P1, T2, P2> Parser<'a, T1> for ChainRight1<T1, P1, T2, P2>
 where
-    P1: Parser<T1>,
-    P2: Parser<T2>,
+    P1: Parser<'a, T1>,
+    P2: Parser<'a, T2>,
     T2: Fn(T1, T1) -> T1,
 {
-    fn parse<'a>(&self, input: &'a str) -> Option<(T1, &'a str)> {
-        if let Some((v, tail1)) = self.0.parse(input) {
-            if let Some((f, tail2)) = self.1.parse(tail1) {
-                if let Some((w, tail3)) = self.parse(tail2) {
-                    Some((f(v, w), tail3))
-                } else {
-                    Some((v, tail1))
-                }
-            } else {
-                Some((v, tail1))
-            }
-        } else {
-            None
+    fn parse(&self, input: &'a str) -> Option<(T1, &'a str)> {
+        // 以前是递归调用self.parse的，运算符一多就爆栈了。现在先把操作数和运算符都存下来，再从右往左合并
+        let (v, mut input) = self.0.parse(input)?;
+        let mut operands = vec![v];
+        let mut operators = vec![];
+
+        while let Some((f, w, tail2)) = state::attempt(|| {
+            let (f, tail1) = self.1.parse(input)?;
+            let (w, tail2) = self.0.parse(tail1)?;
+            Some((f, w, tail2))
+        }) {
+            operators.push(f);
+            operands.push(w);
+            input = tail2;
+        }
+
+        let mut acc = operands.pop()?;
+        while let (Some(f), Some(v)) = (operators.pop(), operands.pop()) {
+            acc = f(v, acc);
         }
+
+        Some((acc, input))
+    }
+}
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+#[derive(Clone)]
+pub struct MaxDepth<P>(P, usize);
+
+impl<'a, T, P> Parser<'a, T> for MaxDepth<P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        let depth = DEPTH.with(|v| v.get());
+        if depth >= self.1 {
+            return None;
+        }
+
+        DEPTH.with(|v| v.set(depth + 1));
+        let _guard = DepthGuard(depth);
+        self.0.parse(input)
+    }
+}
+
+// 里面的parser panic了也要把深度还原，不然这个线程以后的解析都会提前失败
+struct DepthGuard(usize);
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|v| v.set(self.0));
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::is_digit_ascii_radix)]
 mod tests {
     use super::*;
 
@@ -1161,6 +2156,77 @@ mod tests {
         assert_eq!(dbg!(parser.map(|v: String| v).parse(input)), None); // 也可以这样写
     }
 
+    #[test]
+    fn repeat_range() {
+        let parser = hex_digit.repeat(2..=4).map(|v: String| v);
+        assert_eq!(dbg!(parser.parse("a")), None);
+        assert_eq!(dbg!(parser.parse("ab")), Some(("ab".to_owned(), "")));
+        assert_eq!(
+            dbg!(parser.parse("abcdef")),
+            Some(("abcd".to_owned(), "ef"))
+        );
+
+        let parser = digit.repeat(..3).map(|v: Vec<char>| v);
+        assert_eq!(dbg!(parser.parse("x")), Some((vec![], "x")));
+        assert_eq!(dbg!(parser.parse("1234")), Some((vec!['1', '2'], "34")));
+
+        let parser = digit.repeat(1..).map(|v: String| v);
+        assert_eq!(dbg!(parser.parse("1234x")), Some(("1234".to_owned(), "x")));
+        assert_eq!(dbg!(parser.parse("x")), None);
+
+        let parser = epsilon.repeat(3..=3).map(|v: Vec<()>| v.len());
+        assert_eq!(dbg!(parser.parse("x")), Some((3, "x")));
+    }
+
+    #[test]
+    fn repeat_no_progress_minimum() {
+        // 下限以内不吃输入也没关系，次数是定好的
+        let parser = epsilon.repeat(2..=2).map(|v: Vec<()>| v.len());
+        assert_eq!(dbg!(parser.parse("x")), Some((2, "x")));
+        let parser = char('a')
+            .optional()
+            .map(|v| v.unwrap_or('-'))
+            .repeat(3..=3)
+            .map(|v: String| v);
+        assert_eq!(dbg!(parser.parse("ab")), Some(("a--".to_owned(), "b")));
+        let parser = epsilon.repeat(..=1).map(|v: Vec<()>| v.len());
+        assert_eq!(dbg!(parser.parse("x")), Some((1, "x")));
+
+        let parser = digit
+            .repeat((Bound::Excluded(1), Bound::Included(3)))
+            .map(|v: String| v);
+        assert_eq!(dbg!(parser.parse("1234")), Some(("123".to_owned(), "4")));
+        assert_eq!(dbg!(parser.parse("1x")), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty range")]
+    fn repeat_excluded_start_overflow() {
+        digit.repeat((Bound::Excluded(usize::MAX), Bound::Unbounded));
+    }
+
+    #[test]
+    fn repeat_ipv4() {
+        let octet = |input| -> Option<(u8, &str)> {
+            let (v, remaining) = digit.repeat(1..=3).map(|v: String| v).parse(input)?;
+            Some((v.parse().ok()?, remaining))
+        };
+        let parser = octet.separated_by(char('.')).left(eof);
+        assert_eq!(
+            dbg!(parser.parse("192.168.0.1")),
+            Some((vec![192, 168, 0, 1], ""))
+        );
+        assert_eq!(dbg!(parser.parse("256.0.0.1")), None);
+        assert_eq!(dbg!(parser.parse("1.0001")), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty range")]
+    fn repeat_empty_range() {
+        let max = 0;
+        digit.repeat(..max);
+    }
+
     #[test]
     fn parentheses_surrounding_digits() {
         let input = "(1234)";
@@ -1195,6 +2261,40 @@ mod tests {
         assert_eq!(dbg!(parser.parse(input)), None);
     }
 
+    #[test]
+    fn peek_value() {
+        let parser = peek(string("12")).map(|v| v.len());
+        assert_eq!(dbg!(parser.parse("1234")), Some((2, "1234")));
+        let parser = char('1').followed_by(char('2'));
+        assert_eq!(dbg!(parser.parse("1234")), Some(('1', "234")));
+        assert_eq!(dbg!(parser.parse("13")), None);
+    }
+
+    #[test]
+    fn not_succeed_without_consuming() {
+        let parser = not(char('a'));
+        assert_eq!(dbg!(parser.parse("bc")), Some(((), "bc")));
+        assert_eq!(dbg!(parser.parse("")), Some(((), "")));
+        assert_eq!(dbg!(parser.parse("abc")), None);
+    }
+
+    #[test]
+    fn not_followed_by_maximal_munch() {
+        let less = string("<").not_followed_by(one_of(&['=', '<']));
+        let operator = string("<=").choice(string("<<")).choice(less.clone());
+        let parser = operator.lexeme().many();
+        assert_eq!(
+            dbg!(parser.parse("< <= << <")),
+            Some((vec!["<", "<=", "<<", "<"], ""))
+        );
+        assert_eq!(dbg!(less.parse("<=")), None);
+
+        // 关键字后面不能跟着标识符字符
+        let parser = string("if").not_followed_by(alphanumeric);
+        assert_eq!(dbg!(parser.parse("if(x)")), Some(("if", "(x)")));
+        assert_eq!(dbg!(parser.parse("ifx")), None);
+    }
+
     #[test]
     fn epsilon_empty_string() {
         let input = "";
@@ -1355,5 +2455,412 @@ mod tests {
         assert_eq!(dbg!(parser.parse("-1-2-3")), Some((0, ""))); // (-1) - ((-2) - 3)
     }
 
+    #[test]
+    fn chain_right1_many_operators() {
+        let number = integer.map(|v: i64| v);
+        let operator = char('^').map(|_| (|v: i64, w: i64| v.max(w) + 1) as fn(i64, i64) -> i64);
+        let parser = number.chain_right1(operator);
+        let input = "0^".repeat(300000) + "0";
+        assert_eq!(parser.parse(input.as_str()), Some((300000, "")));
+        assert_eq!(dbg!(parser.parse("0^0^")), Some((1, "^")));
+    }
+
+    #[test]
+    fn max_depth_nested_parentheses() {
+        fn s(input: &str) -> Option<((), &str)> {
+            eof.choice(
+                string("()")
+                    .map(|_| ())
+                    .choice(s.between(char('('), char(')')))
+                    .many()
+                    .map(|_| ()),
+            )
+            .max_depth(100)
+            .parse(input)
+        }
+
+        let parser = s.right(eof);
+        let input = "(".repeat(50) + &")".repeat(50);
+        assert_eq!(parser.parse(input.as_str()), Some(((), "")));
+        let input = "(".repeat(100000) + &")".repeat(100000);
+        assert_eq!(parser.parse(input.as_str()), None);
+        assert_eq!(dbg!(parser.parse("(())")), Some(((), ""))); // 失败之后计数器也要复原
+    }
+
+    #[test]
+    fn max_depth_restored_after_panic() {
+        let parser = (|_: &str| -> Option<((), &str)> { panic!("boom") }).max_depth(1);
+        assert!(std::panic::catch_unwind(|| parser.parse("x")).is_err());
+        assert_eq!(dbg!(char('x').max_depth(1).parse("x")), Some(('x', "")));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Many")]
+    fn many_no_progress_panic() {
+        let parser = epsilon.many();
+        let _: Option<(Vec<()>, &str)> = parser.parse("abc");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Many1")]
+    fn many1_no_progress_panic() {
+        let parser = whitespaces.many1();
+        let _: Option<(Vec<()>, &str)> = parser.parse("abc");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "SeparatedBy")]
+    fn separated_by_no_progress_panic() {
+        let parser = epsilon.separated_by(whitespaces);
+        let _ = parser.parse("abc");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "SeparatedEndBy")]
+    fn separated_end_by_no_progress_panic() {
+        let parser = epsilon.separated_end_by(epsilon);
+        let _ = parser.parse("abc");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Many")]
+    fn end_by_no_progress_panic() {
+        let parser = epsilon.end_by(whitespaces);
+        let _ = parser.parse("abc");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Repeat")]
+    fn repeat_no_progress_panic() {
+        let parser = epsilon.repeat(0..);
+        let _: Option<(Vec<()>, &str)> = parser.parse("abc");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Repeat")]
+    fn repeat_bounded_no_progress_panic() {
+        // 上限很大也一样
+        let parser = char('a')
+            .optional()
+            .map(|v| v.unwrap_or('-'))
+            .repeat(0..1000);
+        let _: Option<(String, &str)> = parser.parse("aab");
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn repeat_no_progress_stop() {
+        let parser = epsilon.repeat(0..).map(|v: Vec<()>| v.len());
+        assert_eq!(dbg!(parser.parse("x")), Some((1, "x")));
+        let parser = epsilon.repeat(2..=usize::MAX - 1).map(|v: Vec<()>| v.len());
+        assert_eq!(dbg!(parser.parse("x")), Some((3, "x")));
+        let parser = char('a')
+            .optional()
+            .map(|v| v.unwrap_or('-'))
+            .repeat(0..1000)
+            .map(|v: String| v);
+        assert_eq!(dbg!(parser.parse("aab")), Some(("aa-".to_owned(), "b")));
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn many_no_progress_stop() {
+        assert_eq!(dbg!(epsilon.many().parse("abc")), Some((vec![()], "abc")));
+        assert_eq!(
+            dbg!(whitespaces.many1().parse("abc")),
+            Some((vec![()], "abc"))
+        );
+        assert_eq!(
+            dbg!(epsilon.separated_by(whitespaces).parse("abc")),
+            Some((vec![(), ()], "abc"))
+        );
+        assert_eq!(
+            dbg!(epsilon.separated_end_by(epsilon).parse("abc")),
+            Some((vec![(), ()], "abc"))
+        );
+    }
+
+    #[test]
+    fn many_consuming_nothing_at_end() {
+        // 内层parser失败不算卡住
+        let parser = digit.many1().map(|v: String| v).left(whitespaces).many();
+        assert_eq!(
+            dbg!(parser.parse("1 23 456")),
+            Some((vec!["1".to_owned(), "23".to_owned(), "456".to_owned()], ""))
+        );
+    }
+
+    #[test]
+    fn optional_sign() {
+        let parser = sign.optional();
+        assert_eq!(dbg!(parser.parse("-1")), Some((Some('-'), "1")));
+        assert_eq!(dbg!(parser.parse("+1")), Some((Some('+'), "1")));
+        assert_eq!(dbg!(parser.parse("1")), Some((None, "1")));
+    }
+
+    #[test]
+    fn skip_optional_trailing_comma() {
+        let parser = integer
+            .lexeme()
+            .separated_by(char(',').lexeme())
+            .left(char(',').skip_optional());
+        assert_eq!(dbg!(parser.parse("1, 2, 3,")), Some((vec![1, 2, 3], "")));
+        assert_eq!(dbg!(parser.parse("1, 2, 3")), Some((vec![1, 2, 3], "")));
+    }
+
+    #[test]
+    fn or_default_and_or() {
+        let parser = digit.many1().map(|v: String| v).or_default();
+        assert_eq!(dbg!(parser.parse("12a")), Some(("12".to_owned(), "a")));
+        assert_eq!(dbg!(parser.parse("a")), Some(("".to_owned(), "a")));
+
+        let parser = sign.or('+');
+        assert_eq!(dbg!(parser.parse("-1")), Some(('-', "1")));
+        assert_eq!(dbg!(parser.parse("1")), Some(('+', "1")));
+    }
+
+    #[test]
+    fn many_till_block_comment() {
+        let parser = string("/*").right(any.many_till(string("*/")));
+        assert_eq!(
+            dbg!(parser.parse("/* a * b */c")),
+            Some((" a * b ".to_owned(), "c"))
+        );
+        assert_eq!(dbg!(Parser::<String>::parse(&parser, "/* a")), None);
+
+        let parser = integer.lexeme().many_till(char(';'));
+        assert_eq!(dbg!(parser.parse("1 2 3;4")), Some((vec![1, 2, 3], "4")));
+        assert_eq!(dbg!(parser.parse(";")), Some((vec![], "")));
+        assert_eq!(dbg!(parser.parse("1 a;")), None);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "ManyTill")]
+    fn many_till_no_progress_panic() {
+        let parser = epsilon.many_till(char(';'));
+        let _ = parser.parse("abc");
+    }
+
+    #[test]
+    fn skip_until_end() {
+        let parser = string("/*").right(skip_until(string("*/")));
+        assert_eq!(dbg!(parser.parse("/* 注释 */ 1")), Some(((), " 1")));
+        assert_eq!(dbg!(parser.parse("/* 注释")), None);
+    }
+
+    #[test]
+    fn take_until_pattern() {
+        let parser = take_until(string("\nEOF"));
+        let input = String::from("line 1\nλ line 2\nEOF\n");
+        let (body, remaining) = parser.parse(input.as_str()).unwrap();
+        assert_eq!(body, "line 1\nλ line 2");
+        assert_eq!(remaining, "\nEOF\n");
+        assert_eq!(dbg!(parser.parse("no end")), None);
+        assert_eq!(dbg!(take_until(eof).parse("λλ")), Some(("λλ", "")));
+    }
+
+    #[test]
+    fn recognize_identifier() {
+        let identifier = satisfy(|c| c.is_alphabetic() || c == '_')
+            .right(satisfy(|c| c.is_alphanumeric() || c == '_').many())
+            .map(|_: Vec<char>| ());
+        let parser = recognize(identifier);
+        assert_eq!(dbg!(parser.parse("λ_1 = 2")), Some(("λ_1", " = 2")));
+        assert_eq!(dbg!(parser.parse("1")), None);
+
+        let parser = recognize(
+            sign.skip_optional()
+                .right(digit.many1().map(|_: String| ())),
+        );
+        assert_eq!(dbg!(parser.parse("-123+")), Some(("-123", "+")));
+        assert_eq!(dbg!(recognize(epsilon).parse("abc")), Some(("", "abc")));
+    }
+
+    #[test]
+    fn take_while_digits() {
+        let parser = take_while(|c| c.is_ascii_digit());
+        assert_eq!(dbg!(parser.parse("1234abc")), Some(("1234", "abc")));
+        assert_eq!(dbg!(parser.parse("abc")), Some(("", "abc")));
+        assert_eq!(dbg!(parser.parse("1234")), Some(("1234", "")));
+
+        let parser = take_while1(|c| c.is_alphabetic());
+        assert_eq!(dbg!(parser.parse("λx1")), Some(("λx", "1")));
+        assert_eq!(dbg!(parser.parse("1")), None);
+    }
+
+    #[test]
+    fn take_while_m_n_hex() {
+        let parser = take_while_m_n(2, 4, |c| c.is_ascii_hexdigit());
+        assert_eq!(dbg!(parser.parse("fF")), Some(("fF", "")));
+        assert_eq!(dbg!(parser.parse("12345")), Some(("1234", "5")));
+        assert_eq!(dbg!(parser.parse("1g")), None);
+        assert_eq!(
+            dbg!(take_while_m_n(0, 0, |_| true).parse("a")),
+            Some(("", "a"))
+        );
+    }
+
+    #[test]
+    fn string_non_ascii() {
+        let parser = string("λx");
+        assert_eq!(dbg!(parser.parse("λx.x")), Some(("λx", ".x")));
+        assert_eq!(dbg!(parser.parse("λy.y")), None);
+        assert_eq!(dbg!(parser.parse("λ")), None);
+        assert_eq!(dbg!(string("ab").parse("ba")), None);
+        assert_eq!(dbg!(string("").parse("ab")), Some(("", "ab")));
+    }
+
+    #[test]
+    fn string_owned_pattern() {
+        let keywords: Vec<String> = vec!["let", "in"]
+            .into_iter()
+            .map(|v| format!("{}!", v))
+            .collect();
+        let parser = string(keywords[0].clone()).choice(string(keywords[1].as_str()));
+        assert_eq!(dbg!(parser.parse("in! x")), Some(("in!", " x")));
+        assert_eq!(dbg!(parser.parse("let! x")), Some(("let!", " x")));
+        assert_eq!(dbg!(parser.parse("let x")), None);
+    }
+
+    #[test]
+    fn string_no_case_keyword() {
+        let parser = string_no_case("select");
+        assert_eq!(dbg!(parser.parse("SeLeCt *")), Some(("SeLeCt", " *")));
+        assert_eq!(dbg!(parser.parse("selec")), None);
+        assert_eq!(
+            dbg!(string_no_case("ΣΑΣ").parse("σας!")),
+            Some(("σας", "!"))
+        );
+    }
+
+    #[test]
+    fn decimal_overflow() {
+        assert_eq!(
+            dbg!(decimal.parse("99999999999999999999")),
+            None::<(i32, &str)>
+        );
+        assert_eq!(
+            dbg!(decimal.parse("99999999999999999999")),
+            Some((99999999999999999999u128, ""))
+        );
+        assert_eq!(dbg!(integer.parse("-99999999999")), None::<(i32, &str)>);
+        assert_eq!(dbg!(integer.parse("-  12")), Some((-12i8, "")));
+    }
+
+    #[test]
+    fn checked_decimal_bounds() {
+        assert_eq!(
+            dbg!(checked_decimal::<u8>().parse("255a")),
+            Some((255, "a"))
+        );
+        assert_eq!(dbg!(checked_decimal::<u8>().parse("256")), None);
+        assert_eq!(dbg!(checked_decimal::<u8>().parse("-1")), None);
+        assert_eq!(dbg!(checked_decimal::<u64>().parse("007")), Some((7, "")));
+        assert_eq!(
+            dbg!(checked_decimal::<u128>().parse("340282366920938463463374607431768211455")),
+            Some((u128::MAX, ""))
+        );
+        assert_eq!(
+            dbg!(checked_decimal::<u128>().parse("340282366920938463463374607431768211456")),
+            None
+        );
+    }
+
+    #[test]
+    fn checked_integer_bounds() {
+        let parser = checked_integer::<i8>();
+        assert_eq!(dbg!(parser.parse("-128")), Some((-128, "")));
+        assert_eq!(dbg!(parser.parse("+127")), Some((127, "")));
+        assert_eq!(dbg!(parser.parse("127")), Some((127, "")));
+        assert_eq!(dbg!(parser.parse("128")), None);
+        assert_eq!(dbg!(parser.parse("-129")), None);
+        assert_eq!(dbg!(parser.parse("- 1")), None);
+        assert_eq!(dbg!(checked_integer::<u32>().parse("-0")), Some((0, "")));
+        assert_eq!(dbg!(checked_integer::<u32>().parse("-1")), None);
+        assert_eq!(
+            dbg!(checked_integer::<i128>().parse("-170141183460469231731687303715884105728")),
+            Some((i128::MIN, ""))
+        );
+    }
+
+    #[test]
+    fn checked_integer_separator() {
+        let parser = checked_integer::<i64>().separator('_');
+        assert_eq!(dbg!(parser.parse("1_000_000")), Some((1000000, "")));
+        assert_eq!(dbg!(parser.parse("-1_0")), Some((-10, "")));
+        assert_eq!(dbg!(parser.parse("1_")), Some((1, "_")));
+        assert_eq!(dbg!(parser.parse("1__0")), Some((1, "__0")));
+        assert_eq!(dbg!(parser.parse("_1")), None);
+        assert_eq!(
+            dbg!(checked_integer::<i64>().parse("1_000")),
+            Some((1, "_000"))
+        );
+    }
+
+    #[test]
+    fn radix_integers() {
+        assert_eq!(dbg!(hex_integer::<u32>().parse("0xFFg")), Some((255, "g")));
+        assert_eq!(dbg!(hex_integer::<u32>().parse("0XfF")), Some((255, "")));
+        assert_eq!(dbg!(hex_integer::<u32>().parse("FF")), None);
+        assert_eq!(dbg!(hex_integer::<u32>().parse("0x")), None);
+        assert_eq!(dbg!(hex_integer::<u8>().parse("0x100")), None);
+        assert_eq!(dbg!(oct_integer::<u16>().parse("0o17")), Some((15, "")));
+        assert_eq!(dbg!(oct_integer::<u16>().parse("0o8")), None);
+        assert_eq!(
+            dbg!(bin_integer::<i8>().parse("0b1010_2")),
+            Some((10, "_2"))
+        );
+        assert_eq!(
+            dbg!(bin_integer::<u8>().separator('_').parse("0b1111_0000")),
+            Some((240, ""))
+        );
+        assert_eq!(dbg!(radix_integer::<u64>(36).parse("zZ")), Some((1295, "")));
+        assert_eq!(dbg!(radix_integer::<u64>(3).parse("1223")), Some((17, "3")));
+    }
+
+    #[test]
+    #[should_panic(expected = "radix must be in 2..=36")]
+    fn radix_integer_bad_radix() {
+        radix_integer::<u64>(37);
+    }
+
+    #[test]
+    fn int_literal_prefixes() {
+        let parser = int_literal::<i64>().separator('_');
+        assert_eq!(dbg!(parser.parse("0xFF")), Some((255, "")));
+        assert_eq!(dbg!(parser.parse("-0o17")), Some((-15, "")));
+        assert_eq!(dbg!(parser.parse("+0b1010")), Some((10, "")));
+        assert_eq!(dbg!(parser.parse("1_000")), Some((1000, "")));
+        assert_eq!(dbg!(parser.parse("0")), Some((0, "")));
+        assert_eq!(dbg!(parser.parse("16#FF#")), Some((255, "")));
+        assert_eq!(dbg!(parser.parse("2#1010#;")), Some((10, ";")));
+        assert_eq!(dbg!(parser.parse("16#FF")), Some((16, "#FF")));
+        assert_eq!(dbg!(parser.parse("17#FF#")), Some((17, "#FF#")));
+        assert_eq!(dbg!(parser.parse("0xg")), Some((0, "xg")));
+        assert_eq!(dbg!(parser.parse("-0b2")), Some((0, "b2")));
+        assert_eq!(dbg!(parser.parse("16#FFFFFFFFFFFFFFFFFF#")), None);
+        assert_eq!(dbg!(int_literal::<i8>().parse("-0x80")), Some((-128, "")));
+        assert_eq!(dbg!(int_literal::<i8>().parse("0x80")), None);
+        // 前缀不单独算一步，也不出现在补全里
+        assert_eq!(
+            dbg!(parser.parse_with_budget(1, "0XFF")),
+            Ok(Some((255, "")))
+        );
+        assert_eq!(
+            dbg!(parser.parse_with_budget(1, "16#FF#")),
+            Ok(Some((255, "")))
+        );
+        assert!(completions(&parser, "").is_empty());
+        assert!(completions(&parser, "0").is_empty());
+    }
+
     // 更复杂的全功能计算器在examples/arithmetic.rs里
 }
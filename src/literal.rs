@@ -0,0 +1,227 @@
+use crate::Parser;
+use std::borrow::Cow;
+
+#[derive(Clone)]
+pub struct StringLiteral {
+    quote: char,
+    raw: bool,
+}
+
+/// a string between double quotes, with escape sequences
+///
+/// supports `\n`, `\t`, `\r`, `\0`, `\\`, `\"`, `\'`, `\/`, `\b`, `\f`, `\x7F`, `\u{1F600}` and `\uXXXX` (with
+/// surrogate pairs like JSON). Borrows the input when there is no escape sequence.
+pub fn string_literal() -> StringLiteral {
+    StringLiteral {
+        quote: '"',
+        raw: false,
+    }
+}
+
+/// a raw string like `r"..."` or `r#"..."#`, no escape sequences
+pub fn raw_string_literal() -> StringLiteral {
+    StringLiteral {
+        quote: '"',
+        raw: true,
+    }
+}
+
+impl StringLiteral {
+    /// use another quote character, like ' for 'single quoted' strings
+    pub fn quote(self, quote: char) -> Self {
+        Self { quote, ..self }
+    }
+
+    fn parse_raw<'a>(&self, input: &'a str) -> Option<(Cow<'a, str>, &'a str)> {
+        let input = input.strip_prefix('r')?;
+        let hashes = input.len() - input.trim_start_matches('#').len();
+        let input = input[hashes..].strip_prefix(self.quote)?;
+
+        // r#"..."#的结尾是引号加上同样多的#
+        let end = format!("{}{}", self.quote, "#".repeat(hashes));
+        let i = input.find(end.as_str())?;
+        Some((Cow::Borrowed(&input[..i]), &input[i + end.len()..]))
+    }
+}
+
+// \u后面4个十六进制数字
+fn hex4(input: &str) -> Option<(u32, &str)> {
+    let digits = input.get(..4)?;
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some((u32::from_str_radix(digits, 16).ok()?, &input[4..]))
+}
+
+// 反斜杠后面的部分，返回转义出来的字符
+fn escape(input: &str) -> Option<(char, &str)> {
+    let mut chars = input.chars();
+    let c = chars.next()?;
+    let input = chars.as_str();
+
+    let c = match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        'b' => '\u{8}',
+        'f' => '\u{c}',
+        '\\' | '"' | '\'' | '/' => c,
+        'x' => {
+            let digits = input.get(..2)?;
+            if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            // 和Rust一样，\x只能表示ASCII
+            let v = u8::from_str_radix(digits, 16).ok().filter(|v| *v <= 0x7f)?;
+            return Some((v as char, &input[2..]));
+        }
+        'u' => {
+            if let Some(input) = input.strip_prefix('{') {
+                let i = input.find('}')?;
+                let digits = &input[..i];
+                if digits.is_empty()
+                    || digits.len() > 6
+                    || !digits.chars().all(|c| c.is_ascii_hexdigit())
+                {
+                    return None;
+                }
+                let c = std::char::from_u32(u32::from_str_radix(digits, 16).ok()?)?;
+                return Some((c, &input[i + 1..]));
+            }
+
+            let (high, input) = hex4(input)?;
+            if (0xd800..0xdc00).contains(&high) {
+                // UTF-16的代理对，后面一定要跟着一个低位的\uXXXX
+                let (low, input) = hex4(input.strip_prefix("\\u")?)?;
+                if !(0xdc00..0xe000).contains(&low) {
+                    return None;
+                }
+                let c = std::char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))?;
+                return Some((c, input));
+            }
+            return Some((std::char::from_u32(high)?, input)); // 单独的低位代理在这里会失败
+        }
+        _ => return None,
+    };
+
+    Some((c, input))
+}
+
+impl StringLiteral {
+    fn literal<'a>(&self, input: &'a str) -> Option<(Cow<'a, str>, &'a str)> {
+        if self.raw {
+            return self.parse_raw(input);
+        }
+
+        let body = input.strip_prefix(self.quote)?;
+        let i = body.find([self.quote, '\\'])?;
+        if body[i..].starts_with(self.quote) {
+            // 没有转义的话直接借用输入
+            return Some((
+                Cow::Borrowed(&body[..i]),
+                &body[i + self.quote.len_utf8()..],
+            ));
+        }
+
+        let mut target = String::from(&body[..i]);
+        let mut input = &body[i..];
+
+        loop {
+            let mut chars = input.chars();
+            match chars.next()? {
+                '\\' => {
+                    let (c, remaining) = escape(chars.as_str())?;
+                    target.push(c);
+                    input = remaining;
+                }
+                c if c == self.quote => break Some((Cow::Owned(target), chars.as_str())),
+                c => {
+                    target.push(c);
+                    input = chars.as_str();
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Parser<'a, Cow<'a, str>> for StringLiteral {
+    fn parse(&self, input: &'a str) -> Option<(Cow<'a, str>, &'a str)> {
+        crate::budget::primitive(|| {
+            let (v, remaining) = self.literal(input)?;
+            crate::cst::token(|| crate::cst::STRING.into(), input, remaining);
+            Some((v, remaining))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn string_literal_borrowed() {
+        let parser = string_literal();
+        let (v, remaining) = parser.parse("\"hello λ\" world").unwrap();
+        assert_eq!(v, "hello λ");
+        assert!(matches!(v, Cow::Borrowed(_)));
+        assert_eq!(remaining, " world");
+        assert_eq!(dbg!(parser.parse("\"\"")), Some((Cow::Borrowed(""), "")));
+        assert_eq!(dbg!(parser.parse("\"unterminated")), None);
+        assert_eq!(dbg!(parser.parse("'single'")), None);
+    }
+
+    #[test]
+    fn string_literal_escapes() {
+        let parser = string_literal();
+        let (v, remaining) = parser
+            .parse(r#""a\n\t\\\"\'\/\0\x41\u{1F600}\u00e9" rest"#)
+            .unwrap();
+        assert_eq!(v, "a\n\t\\\"'/\0A😀é");
+        assert!(matches!(v, Cow::Owned(_)));
+        assert_eq!(remaining, " rest");
+        assert_eq!(dbg!(parser.parse(r#""\q""#)), None);
+        assert_eq!(dbg!(parser.parse(r#""\x80""#)), None);
+        assert_eq!(dbg!(parser.parse(r#""\u{110000}""#)), None);
+        assert_eq!(dbg!(parser.parse(r#""\u{}""#)), None);
+        assert_eq!(dbg!(parser.parse(r#""\u12""#)), None);
+        assert_eq!(dbg!(parser.parse(r#""abc\n"#)), None);
+    }
+
+    #[test]
+    fn string_literal_surrogate_pair() {
+        let parser = string_literal();
+        assert_eq!(
+            dbg!(parser.parse(r#""\ud83d\ude00""#)),
+            Some((Cow::Owned("😀".to_owned()), ""))
+        );
+        assert_eq!(dbg!(parser.parse(r#""\ud83d""#)), None);
+        assert_eq!(dbg!(parser.parse(r#""\ud83d\u0041""#)), None);
+        assert_eq!(dbg!(parser.parse(r#""\ude00""#)), None);
+    }
+
+    #[test]
+    fn string_literal_single_quote() {
+        let parser = string_literal().quote('\'');
+        assert_eq!(
+            dbg!(parser.parse(r#"'it\'s "quoted"'"#)),
+            Some((Cow::Owned("it's \"quoted\"".to_owned()), ""))
+        );
+        assert_eq!(dbg!(parser.parse("\"double\"")), None);
+    }
+
+    #[test]
+    fn raw_string() {
+        let parser = raw_string_literal();
+        assert_eq!(
+            dbg!(parser.parse(r#"r"C:\path" x"#)),
+            Some((Cow::Borrowed(r"C:\path"), " x"))
+        );
+        assert_eq!(
+            dbg!(parser.parse(r###"r##"a "# b"##"###)),
+            Some((Cow::Borrowed(r##"a "# b"##), ""))
+        );
+        assert_eq!(dbg!(parser.parse(r###"r##"a"#"###)), None);
+        assert_eq!(dbg!(parser.parse("\"not raw\"")), None);
+    }
+}
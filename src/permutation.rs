@@ -0,0 +1,166 @@
+use crate::state;
+use crate::Parser;
+
+/// something that can appear in a permutation, either a parser that must appear or opt(p) that may not
+pub trait Permute<'a, T> {
+    fn parse_member(&self, input: &'a str) -> Option<(T, &'a str)>;
+
+    // 一直没出现的时候用什么值，必须出现的就是None
+    fn missing(&self) -> Option<T>;
+}
+
+impl<'a, T, P> Permute<'a, T> for P
+where
+    P: Parser<'a, T>,
+{
+    fn parse_member(&self, input: &'a str) -> Option<(T, &'a str)> {
+        self.parse(input)
+    }
+
+    fn missing(&self) -> Option<T> {
+        None
+    }
+}
+
+#[derive(Clone)]
+pub struct Opt<P>(P);
+
+// Opt故意不实现Parser，不然和上面的impl冲突。p.optional()不能用在这里，因为它什么都不吃也会成功，会在第一个位置就把空位占掉
+impl<'a, T, P> Permute<'a, Option<T>> for Opt<P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse_member(&self, input: &'a str) -> Option<(Option<T>, &'a str)> {
+        self.0
+            .parse(input)
+            .map(|(a, remaining)| (Some(a), remaining))
+    }
+
+    fn missing(&self) -> Option<Option<T>> {
+        Some(None)
+    }
+}
+
+/// a member of permutation that may be absent, return None if so
+pub fn opt<P>(p: P) -> Opt<P> {
+    Opt(p)
+}
+
+#[derive(Clone)]
+pub struct Permutation<P>(P);
+
+/// match every parser in the tuple once in any order, return their results in the order of the tuple
+///
+/// members wrapped in opt(p) may be absent. Each member is tried at the current position in tuple order, the first
+/// one that succeeds takes it. Fail if a required member never appears.
+pub fn permutation<P>(parsers: P) -> Permutation<P> {
+    Permutation(parsers)
+}
+
+macro_rules! impl_permutation {
+    ($(($T:ident, $P:ident, $v:ident, $i:tt)),+) => {
+        impl<'a, $($T, $P),+> Parser<'a, ($($T,)+)> for Permutation<($($P,)+)>
+        where
+            $($P: Permute<'a, $T>),+
+        {
+            fn parse(&self, input: &'a str) -> Option<(($($T,)+), &'a str)> {
+                let mut input = input;
+                $(let mut $v: Option<$T> = None;)+
+
+                // 每一轮把还没出现过的挨个试一遍，一轮下来谁都匹配不上就结束
+                loop {
+                    let mut progress = false;
+                    $(
+                        if $v.is_none() {
+                            if let Some((a, remaining)) = state::attempt(|| (self.0).$i.parse_member(input)) {
+                                $v = Some(a);
+                                input = remaining;
+                                progress = true;
+                            }
+                        }
+                    )+
+                    if !progress {
+                        break;
+                    }
+                }
+
+                Some((($($v.or_else(|| (self.0).$i.missing())?,)+), input))
+            }
+        }
+    };
+}
+
+impl_permutation!((T1, P1, v1, 0));
+impl_permutation!((T1, P1, v1, 0), (T2, P2, v2, 1));
+impl_permutation!((T1, P1, v1, 0), (T2, P2, v2, 1), (T3, P3, v3, 2));
+impl_permutation!(
+    (T1, P1, v1, 0),
+    (T2, P2, v2, 1),
+    (T3, P3, v3, 2),
+    (T4, P4, v4, 3)
+);
+impl_permutation!(
+    (T1, P1, v1, 0),
+    (T2, P2, v2, 1),
+    (T3, P3, v3, 2),
+    (T4, P4, v4, 3),
+    (T5, P5, v5, 4)
+);
+impl_permutation!(
+    (T1, P1, v1, 0),
+    (T2, P2, v2, 1),
+    (T3, P3, v3, 2),
+    (T4, P4, v4, 3),
+    (T5, P5, v5, 4),
+    (T6, P6, v6, 5)
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{char, integer, none_of, string, symbol};
+
+    #[test]
+    fn permutation_any_order() {
+        let parser = permutation((char('a'), char('b'), char('c')));
+        assert_eq!(dbg!(parser.parse("abc")), Some((('a', 'b', 'c'), "")));
+        assert_eq!(dbg!(parser.parse("cabd")), Some((('a', 'b', 'c'), "d")));
+        assert_eq!(dbg!(parser.parse("bac")), Some((('a', 'b', 'c'), "")));
+        assert_eq!(dbg!(parser.parse("ab")), None);
+        assert_eq!(dbg!(parser.parse("aab")), None);
+    }
+
+    #[test]
+    fn permutation_optional() {
+        let parser = permutation((char('a'), opt(char('b')), char('c')));
+        assert_eq!(dbg!(parser.parse("ca")), Some((('a', None, 'c'), "")));
+        assert_eq!(dbg!(parser.parse("cba")), Some((('a', Some('b'), 'c'), "")));
+        assert_eq!(dbg!(parser.parse("b")), None);
+    }
+
+    #[test]
+    fn permutation_attributes() {
+        let value = none_of(&['"'])
+            .many()
+            .map(|v: String| v)
+            .between(char('"'), symbol("\""));
+        let attribute = |name| symbol(name).right(symbol("=")).right(value.clone());
+        let id = symbol("id")
+            .right(symbol("="))
+            .right(integer.lexeme())
+            .map(|v: u32| v);
+        let parser = permutation((attribute("name"), id, opt(attribute("class"))))
+            .between(symbol("<"), string(">"));
+
+        assert_eq!(
+            dbg!(parser.parse(r#"< class="big" id = 3 name="x">"#)),
+            Some((("x".to_owned(), 3, Some("big".to_owned())), ""))
+        );
+        assert_eq!(
+            dbg!(parser.parse(r#"<id=3 name="x" >"#)),
+            Some((("x".to_owned(), 3, None), ""))
+        );
+        assert_eq!(dbg!(parser.parse(r#"<id=3 id=4 name="x">"#)), None);
+        assert_eq!(dbg!(parser.parse(r#"<class="big" id=3>"#)), None);
+    }
+}
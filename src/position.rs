@@ -0,0 +1,394 @@
+use crate::Parser;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// where something is in the input
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    /// bytes from the start of the input
+    pub offset: usize,
+    /// starting from 1
+    pub line: usize,
+    /// characters from the start of the line, starting from 1
+    pub column: usize,
+}
+
+// 剩下的输入永远是原始输入的后缀，所以只要知道原始输入的长度，就能从剩下的输入的长度算出offset。
+// 行号要看原始输入的内容，每个origin拷贝一次，行号的索引第一次要用的时候才建
+struct Source {
+    len: usize,
+    text: Option<Rc<str>>,     // 只有spanned的时候用不到，不拷贝
+    lines: Option<Vec<usize>>, // 每一行开头的offset，算行号的时候二分
+}
+
+thread_local! {
+    static SOURCES: RefCell<Vec<Source>> = const { RefCell::new(Vec::new()) };
+}
+
+struct SourceGuard;
+
+impl Drop for SourceGuard {
+    fn drop(&mut self) {
+        SOURCES.with(|v| v.borrow_mut().pop());
+    }
+}
+
+fn push(input: &str, text: bool) -> SourceGuard {
+    SOURCES.with(|v| {
+        v.borrow_mut().push(Source {
+            len: input.len(),
+            text: text.then(|| Rc::from(input)),
+            lines: None,
+        })
+    });
+    SourceGuard
+}
+
+// 在f里面，位置都从input的开头算
+pub(crate) fn with_origin<R, F>(input: &str, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _guard = push(input, true);
+    f()
+}
+
+// 外面没有origin的时候，最外层的spanned从自己的输入开头算
+pub(crate) fn anchored<R, F>(input: &str, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    if SOURCES.with(|v| v.borrow().is_empty()) {
+        let _guard = push(input, false);
+        f()
+    } else {
+        f()
+    }
+}
+
+// 和anchored一样，不过要算行号，外面只有spanned留下的长度也不够
+pub(crate) fn anchored_text<R, F>(input: &str, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    if has_origin() {
+        f()
+    } else {
+        let _guard = push(input, true);
+        f()
+    }
+}
+
+// 能不能算出行号
+pub(crate) fn has_origin() -> bool {
+    SOURCES.with(|v| v.borrow().last().is_some_and(|v| v.text.is_some()))
+}
+
+// 只要字节offset的话不用算行号，外面什么都没有就当remaining是开头
+pub(crate) fn offset_of(remaining: &str) -> usize {
+    SOURCES.with(|v| match v.borrow().last() {
+        Some(source) => source.len.saturating_sub(remaining.len()),
+        None => 0,
+    })
+}
+
+// remaining前面到行首只有空白，也就是说它是这一行的第一个东西
+pub(crate) fn starts_line(remaining: &str) -> bool {
+    SOURCES.with(|v| {
+        let sources = v.borrow();
+        let Some((source, text)) = sources.last().and_then(|v| Some((v, v.text.as_ref()?))) else {
+            return true;
+        };
+        let offset = source.len.saturating_sub(remaining.len());
+        let before = text.get(..offset).unwrap_or("");
+        before
+            .rsplit('\n')
+            .next()
+            .is_some_and(|v| v.chars().all(char::is_whitespace))
+    })
+}
+
+/// where remaining starts, line 1 column 1 if nothing says where the text starts
+pub(crate) fn position_of(remaining: &str) -> Position {
+    SOURCES.with(|v| {
+        let mut sources = v.borrow_mut();
+        let Some(source) = sources.last_mut().filter(|v| v.text.is_some()) else {
+            return Position {
+                offset: 0,
+                line: 1,
+                column: 1,
+            };
+        };
+        let text = source.text.clone().unwrap();
+        let offset = source.len.saturating_sub(remaining.len());
+        let lines = source.lines.get_or_insert_with(|| {
+            std::iter::once(0)
+                .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+                .collect()
+        });
+        let line = lines.partition_point(|&start| start <= offset);
+        let start = lines[line - 1];
+        let column = match text.get(start..offset) {
+            Some(v) => v.chars().count(),
+            None => offset - start,
+        };
+
+        Position {
+            offset,
+            line,
+            column: column + 1,
+        }
+    })
+}
+
+/// start and end byte offsets of something in the input, end not included
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// the part of input it covers, input should be what the offsets count from
+    pub fn slice<'a>(&self, input: &'a str) -> &'a str {
+        &input[self.start..self.end]
+    }
+}
+
+/// a value with where it starts and ends in the input
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Located<T> {
+    pub value: T,
+    pub start: Position,
+    pub end: Position,
+}
+
+impl<T> Located<T> {
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.start.offset,
+            end: self.end.offset,
+        }
+    }
+
+    pub fn map<U, F>(self, f: F) -> Located<U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        Located {
+            value: f(self.value),
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Spanned<P>(pub(crate) P);
+
+impl<'a, T, P> Parser<'a, (T, Span)> for Spanned<P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<((T, Span), &'a str)> {
+        anchored(input, || {
+            let start = offset_of(input);
+            let (a, remaining) = self.0.parse(input)?;
+            let end = offset_of(remaining);
+            Some(((a, Span { start, end }), remaining))
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Locate<P>(pub(crate) P);
+
+impl<'a, T, P> Parser<'a, Located<T>> for Locate<P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<(Located<T>, &'a str)> {
+        anchored_text(input, || {
+            let start = position_of(input);
+            let (value, remaining) = self.0.parse(input)?;
+            let end = position_of(remaining);
+            Some((Located { value, start, end }, remaining))
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Origin<P>(pub(crate) P);
+
+impl<'a, T, P> Parser<'a, T> for Origin<P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        with_origin(input, || self.0.parse(input))
+    }
+}
+
+/// match p, positions inside p count from where p starts, which is line 1 column 1
+///
+/// without it, spanned, located, position and the indentation parsers count from where the outermost of them starts,
+/// and trace doesn't know positions. Put one around the whole parser to count from the start of the input.
+pub fn origin<'a, T, P>(p: P) -> Origin<P>
+where
+    P: Parser<'a, T>,
+{
+    Origin(p)
+}
+
+/// the current position, consume no input
+pub fn position(input: &str) -> Option<(Position, &str)> {
+    Some((anchored_text(input, || position_of(input)), input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{char, identifier, integer, symbol, whitespaces};
+
+    #[test]
+    fn spanned_offsets() {
+        let number = integer.map(|v: i64| v).spanned().lexeme();
+        let parser = origin(number.separated_by(symbol(",")));
+        let input = "12, 345 ,6";
+        let (v, _) = parser.parse(input).unwrap();
+        assert_eq!(
+            v,
+            vec![
+                (12, Span { start: 0, end: 2 }),
+                (345, Span { start: 4, end: 7 }),
+                (6, Span { start: 9, end: 10 })
+            ]
+        );
+        assert_eq!(v[1].1.slice(input), "345");
+
+        // 里面的spanned也是从整个输入的开头算
+        let parser = origin(
+            char('(')
+                .right(char('x').spanned())
+                .left(char(')'))
+                .spanned(),
+        );
+        assert_eq!(
+            dbg!(parser.parse("(x)")),
+            Some((
+                (('x', Span { start: 1, end: 2 }), Span { start: 0, end: 3 }),
+                ""
+            ))
+        );
+        let parser = whitespaces.right(char('x').spanned());
+        assert_eq!(
+            dbg!(origin(parser).parse("  x")),
+            Some((('x', Span { start: 2, end: 3 }), ""))
+        );
+    }
+
+    #[test]
+    fn spanned_in_the_middle() {
+        // 最外层的spanned不在输入的开头
+        let parser = origin(whitespaces.right(char('x').spanned()));
+        assert_eq!(
+            dbg!(parser.parse("  x")),
+            Some((('x', Span { start: 2, end: 3 }), ""))
+        );
+
+        let number = integer.map(|v: i64| v).spanned().lexeme();
+        let parser = origin(symbol("[").right(number.separated_by(symbol(","))));
+        let input = "[ 12, 345 ,6";
+        let (v, _) = parser.parse(input).unwrap();
+        assert_eq!(
+            v.iter()
+                .map(|(_, span)| span.slice(input))
+                .collect::<Vec<_>>(),
+            vec!["12", "345", "6"]
+        );
+        assert_eq!(v[2].1, Span { start: 11, end: 12 });
+
+        // 里面再包一层origin就从那里重新算
+        let parser = origin(whitespaces.right(origin(char('x').spanned())));
+        assert_eq!(
+            dbg!(parser.parse("  x")),
+            Some((('x', Span { start: 0, end: 1 }), ""))
+        );
+    }
+
+    #[test]
+    fn spanned_without_origin() {
+        // 没有origin就从最外层的spanned开始算
+        let parser = whitespaces.right(char('(').right(char('x').spanned()).spanned());
+        assert_eq!(
+            dbg!(parser.parse("  (x")),
+            Some((
+                (('x', Span { start: 1, end: 2 }), Span { start: 0, end: 2 }),
+                ""
+            ))
+        );
+        let word = identifier().located();
+        let (v, _) = whitespaces.right(word).parse(" \n  foo").unwrap();
+        assert_eq!((v.start.line, v.start.column, v.end.column), (1, 1, 4));
+    }
+
+    #[test]
+    fn located_line_column() {
+        let word = identifier().located().lexeme();
+        let parser = origin(whitespaces.right(word.many()));
+        let (v, _) = parser.parse("\n  foo\nλ bar").unwrap();
+        assert_eq!(v[0].value, "foo");
+        assert_eq!(
+            (v[0].start, v[0].end),
+            (
+                Position {
+                    offset: 3,
+                    line: 2,
+                    column: 3
+                },
+                Position {
+                    offset: 6,
+                    line: 2,
+                    column: 6
+                }
+            )
+        );
+        assert_eq!(v[1].value, "λ");
+        assert_eq!(v[2].start.column, 3);
+        assert_eq!(v[2].span(), Span { start: 10, end: 13 });
+        assert_eq!(v[2].map(str::len).value, 3);
+    }
+
+    #[test]
+    fn position_line_column() {
+        let parser = origin(
+            char('a')
+                .right(whitespaces)
+                .right(char('λ'))
+                .right(char('b'))
+                .right(position),
+        );
+        assert_eq!(
+            dbg!(parser.parse("a\n\n  λbc")),
+            Some((
+                Position {
+                    offset: 8,
+                    line: 3,
+                    column: 5
+                },
+                "c"
+            ))
+        );
+        assert_eq!(
+            dbg!(origin(position).parse("abc")),
+            Some((
+                Position {
+                    offset: 0,
+                    line: 1,
+                    column: 1
+                },
+                "abc"
+            ))
+        );
+    }
+}
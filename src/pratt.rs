@@ -0,0 +1,681 @@
+use crate::complete::Label;
+use crate::cst::Token;
+use crate::state;
+use crate::trace::Trace;
+use crate::{
+    Any, Char, Choice, Function, Keyword, Left, LookAhead, Map, Not, Parser, Right, Satisfy, Str,
+    StrNoCase, TakeWhile, Trivia, Whitespaces,
+};
+
+// nud: null denotation，运算符出现在表达式开头时怎么处理，比如前缀的-、括号
+// led: left denotation，运算符出现在左操作数后面时怎么处理，比如中缀的+、后缀的!、函数调用
+// 参考 https://matklad.github.io/2020/04/13/simple-but-powerful-pratt-parsing.html
+
+type Nud<'p, T> = Box<dyn for<'a> Fn(&Pratt<'p, T>, &'a str) -> Option<(T, &'a str)> + 'p>;
+// led失败的时候要把左操作数还回来，这样才能像chain_left1那样回退到运算符之前
+type Led<'p, T> = Box<dyn for<'a> Fn(&Pratt<'p, T>, T, &'a str) -> Result<(T, &'a str), T> + 'p>;
+
+// 和function()一样，用来给closure标记higher-ranked lifetime
+fn nud<'p, T, F>(f: F) -> F
+where
+    F: for<'a> Fn(&Pratt<'p, T>, &'a str) -> Option<(T, &'a str)>,
+{
+    f
+}
+
+fn led<'p, T, F>(f: F) -> F
+where
+    F: for<'a> Fn(&Pratt<'p, T>, T, &'a str) -> Result<(T, &'a str), T>,
+{
+    f
+}
+
+/// an operator token of a Pratt table, only how much input it matches matters
+///
+/// implemented for char, string, symbol, keyword and the like, for functions and closures whose result doesn't borrow
+/// the input, and for lexeme, left, right, map, choice, look_ahead, not, label, token and trace of them.
+/// Unlike Parser it has to work on any input, which Parser<'a, &'a str> of string can't promise.
+pub trait Operator {
+    /// the input after the operator
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str>;
+}
+
+// 这些本来就对任何输入都能用，只是返回值可能借用了输入
+macro_rules! impl_operator {
+    ($([$($g:tt)*] $ty:ty),* $(,)?) => {$(
+        impl<$($g)*> Operator for $ty {
+            fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+                Parser::parse(self, input).map(|(_, remaining)| remaining)
+            }
+        }
+    )*};
+}
+
+impl_operator! {
+    [] Any,
+    [] Char,
+    [] Whitespaces,
+    ['b] Str<'b>,
+    ['b] StrNoCase<'b>,
+    ['b] Keyword<'b>,
+    ['b] Trivia<'b>,
+}
+
+// 返回值不借用输入的函数，比如fn(&str) -> Option<((), &str)>
+impl<T, F> Operator for F
+where
+    F: for<'a> Fn(&'a str) -> Option<(T, &'a str)>,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        self(input).map(|(_, remaining)| remaining)
+    }
+}
+
+impl<T, F> Operator for Function<F>
+where
+    F: for<'a> Fn(&'a str) -> Option<(T, &'a str)>,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        self.parse(input).map(|(_, remaining)| remaining)
+    }
+}
+
+impl<F> Operator for Satisfy<F>
+where
+    F: Fn(char) -> bool,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        self.parse(input).map(|(_, remaining)| remaining)
+    }
+}
+
+impl<F> Operator for TakeWhile<F>
+where
+    F: Fn(char) -> bool,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        self.parse(input).map(|(_, remaining)| remaining)
+    }
+}
+
+// 组合起来的只看两边吃了多少，不管T1、T2是什么
+impl<T1, P1, T2, P2> Operator for Left<T1, P1, T2, P2>
+where
+    P1: Operator,
+    P2: Operator,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        self.1.skip(self.0.skip(input)?)
+    }
+}
+
+impl<T1, P1, T2, P2> Operator for Right<T1, P1, T2, P2>
+where
+    P1: Operator,
+    P2: Operator,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        self.1.skip(self.0.skip(input)?)
+    }
+}
+
+impl<T, P, F> Operator for Map<T, P, F>
+where
+    P: Operator,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        self.0.skip(input)
+    }
+}
+
+impl<P1, P2> Operator for Choice<P1, P2>
+where
+    P1: Operator,
+    P2: Operator,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        self.0.skip(input).or_else(|| self.1.skip(input))
+    }
+}
+
+// 和Parser一样不吃输入，也不留下对状态的改动
+impl<P> Operator for LookAhead<P>
+where
+    P: Operator,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        state::peek(|| self.0.skip(input)).map(|_| input)
+    }
+}
+
+impl<T, P> Operator for Not<T, P>
+where
+    P: Operator,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        match state::peek(|| self.0.skip(input)) {
+            Some(_) => None,
+            None => Some(input),
+        }
+    }
+}
+
+// 这几个包了一层的，和Parser一样记补全、记token、打trace
+impl<P> Operator for Label<'_, P>
+where
+    P: Operator,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        self.run(input, || self.0.skip(input))
+    }
+}
+
+impl<P> Operator for Token<P>
+where
+    P: Operator,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        self.run(input, || {
+            self.0.skip(input).map(|remaining| ((), remaining))
+        })
+        .map(|(_, remaining)| remaining)
+    }
+}
+
+impl<P> Operator for Trace<'_, P>
+where
+    P: Operator,
+{
+    fn skip<'a>(&self, input: &'a str) -> Option<&'a str> {
+        self.run(input, || {
+            self.0.skip(input).map(|remaining| ((), remaining))
+        })
+        .map(|(_, remaining)| remaining)
+    }
+}
+
+/// top-down operator precedence parser
+///
+/// every operator token registers a binding power and a handler. Higher binding power binds tighter. Binding powers
+/// should be positive, 0 is reserved for the whole expression.
+///
+/// the table doesn't borrow the input, build it once and use it on any string.
+pub struct Pratt<'p, T> {
+    atom: Box<dyn for<'a> Parser<'a, T> + 'p>,
+    nuds: Vec<(Box<dyn Operator + 'p>, Nud<'p, T>)>,
+    leds: Vec<(Box<dyn Operator + 'p>, u32, Led<'p, T>)>,
+}
+
+/// build a Pratt parser whose operands are matched by atom
+pub fn pratt<'p, T, P>(atom: P) -> Pratt<'p, T>
+where
+    P: for<'a> Parser<'a, T> + 'p,
+{
+    Pratt {
+        atom: Box::new(atom),
+        nuds: vec![],
+        leds: vec![],
+    }
+}
+
+impl<'p, T> Pratt<'p, T>
+where
+    T: 'p,
+{
+    /// register a token that starts an expression, f parses what follows the token
+    ///
+    /// nud handlers are tried in registration order before atom.
+    pub fn nud<O, F>(mut self, operator: O, f: F) -> Self
+    where
+        O: Operator + 'p,
+        F: for<'a> Fn(&Pratt<'p, T>, &'a str) -> Option<(T, &'a str)> + 'p,
+    {
+        self.nuds.push((Box::new(operator), Box::new(f)));
+        self
+    }
+
+    /// register a token that follows a left operand with binding power lbp
+    ///
+    /// rest parses what follows the token, then f combines the left operand with it. If rest fails, the expression
+    /// ends before the token. led handlers are tried in registration order.
+    pub fn led<O, X, R, F>(mut self, operator: O, lbp: u32, rest: R, f: F) -> Self
+    where
+        O: Operator + 'p,
+        R: for<'a> Fn(&Pratt<'p, T>, &'a str) -> Option<(X, &'a str)> + 'p,
+        F: Fn(T, X) -> T + 'p,
+    {
+        let handler = led(move |pratt: &Pratt<'p, T>, lhs, input| {
+            if let Some((x, remaining)) = rest(pratt, input) {
+                Ok((f(lhs, x), remaining))
+            } else {
+                Err(lhs)
+            }
+        });
+        self.leds.push((Box::new(operator), lbp, Box::new(handler)));
+        self
+    }
+
+    /// op p
+    pub fn prefix<O, F>(self, operator: O, bp: u32, f: F) -> Self
+    where
+        O: Operator + 'p,
+        F: Fn(T) -> T + 'p,
+    {
+        let handler = nud(move |pratt: &Pratt<'p, T>, input| {
+            let (v, remaining) = pratt.parse_bp(input, bp)?;
+            Some((f(v), remaining))
+        });
+        self.nud(operator, handler)
+    }
+
+    /// p1 op p2, `a op b op c` is `(a op b) op c`
+    pub fn infix_left<O, F>(self, operator: O, bp: u32, f: F) -> Self
+    where
+        O: Operator + 'p,
+        F: Fn(T, T) -> T + 'p,
+    {
+        self.led(
+            operator,
+            bp,
+            move |pratt, input| pratt.parse_bp(input, bp),
+            f,
+        )
+    }
+
+    /// p1 op p2, `a op b op c` is `a op (b op c)`
+    pub fn infix_right<O, F>(self, operator: O, bp: u32, f: F) -> Self
+    where
+        O: Operator + 'p,
+        F: Fn(T, T) -> T + 'p,
+    {
+        // 右边的操作数允许再出现同样的运算符，所以绑定力要比自己低一点
+        let rbp = bp.saturating_sub(1);
+        self.led(
+            operator,
+            bp,
+            move |pratt, input| pratt.parse_bp(input, rbp),
+            f,
+        )
+    }
+
+    /// p op
+    pub fn postfix<O, F>(self, operator: O, bp: u32, f: F) -> Self
+    where
+        O: Operator + 'p,
+        F: Fn(T) -> T + 'p,
+    {
+        self.led(operator, bp, |_, input| Some(((), input)), move |v, _| f(v))
+    }
+
+    /// p1 ? p2 : p3, right associative
+    pub fn ternary<O1, O2, F>(self, question: O1, colon: O2, bp: u32, f: F) -> Self
+    where
+        O1: Operator + 'p,
+        O2: Operator + 'p,
+        F: Fn(T, T, T) -> T + 'p,
+    {
+        let rbp = bp.saturating_sub(1);
+        self.led(
+            question,
+            bp,
+            move |pratt, input| {
+                let (v, input) = pratt.parse_bp(input, 0)?;
+                let input = colon.skip(input)?;
+                let (w, input) = pratt.parse_bp(input, rbp)?;
+                Some(((v, w), input))
+            },
+            move |u, (v, w)| f(u, v, w),
+        )
+    }
+
+    /// p(p1, p2, ...), a function call
+    pub fn call<O1, O2, O3, F>(self, open: O1, separator: O2, close: O3, bp: u32, f: F) -> Self
+    where
+        O1: Operator + 'p,
+        O2: Operator + 'p,
+        O3: Operator + 'p,
+        F: Fn(T, Vec<T>) -> T + 'p,
+    {
+        self.led(
+            open,
+            bp,
+            move |pratt, input| {
+                let mut input = input;
+                let mut arguments = vec![];
+
+                // 和SeparatedBy一样，但是separator和close只能借用，没法直接组合
+                if let Some((v, remaining)) = pratt.parse_bp(input, 0) {
+                    arguments.push(v);
+                    input = remaining;
+
+                    while let Some(tail1) = separator.skip(input) {
+                        if let Some((v, tail2)) = pratt.parse_bp(tail1, 0) {
+                            arguments.push(v);
+                            input = tail2;
+                        } else {
+                            break;
+                        }
+                    }
+                }
+
+                let input = close.skip(input)?;
+                Some((arguments, input))
+            },
+            f,
+        )
+    }
+
+    /// p1[p2], indexing
+    pub fn index<O1, O2, F>(self, open: O1, close: O2, bp: u32, f: F) -> Self
+    where
+        O1: Operator + 'p,
+        O2: Operator + 'p,
+        F: Fn(T, T) -> T + 'p,
+    {
+        self.led(
+            open,
+            bp,
+            move |pratt, input| {
+                let (v, input) = pratt.parse_bp(input, 0)?;
+                let input = close.skip(input)?;
+                Some((v, input))
+            },
+            f,
+        )
+    }
+
+    /// a parser for expressions whose operators all bind tighter than min_bp
+    ///
+    /// use this in nud and led handlers to parse operands.
+    pub fn expression(&self, min_bp: u32) -> Expression<'_, 'p, T> {
+        Expression(self, min_bp)
+    }
+
+    fn parse_nud<'a>(&self, input: &'a str) -> Option<(T, &'a str)> {
+        for (operator, f) in self.nuds.iter() {
+            // 失败了换下一个之前要把状态还原
+            let res = state::attempt(|| {
+                let remaining = operator.skip(input)?;
+                f(self, remaining)
+            });
+            if res.is_some() {
+                return res;
+            }
+        }
+
+        self.atom.parse(input)
+    }
+
+    fn parse_bp<'a>(&self, input: &'a str, min_bp: u32) -> Option<(T, &'a str)> {
+        let (mut lhs, mut input) = self.parse_nud(input)?;
+
+        'outer: loop {
+            for (operator, lbp, f) in self.leds.iter() {
+                // 绑定得不够紧或者后面解析不了，就试下一个，比如*失败了还有**
+                if *lbp <= min_bp {
+                    continue;
+                }
+
+                let res = state::attempt_ok(|| match operator.skip(input) {
+                    Some(remaining) => f(self, lhs, remaining),
+                    None => Err(lhs),
+                });
+                match res {
+                    Ok((v, remaining)) => {
+                        lhs = v;
+                        input = remaining;
+                        continue 'outer;
+                    }
+                    Err(v) => lhs = v, // 回退到运算符之前，状态也一样
+                }
+            }
+
+            break;
+        }
+
+        Some((lhs, input))
+    }
+}
+
+impl<'a, 'p, T> Parser<'a, T> for Pratt<'p, T>
+where
+    T: 'p,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        self.parse_bp(input, 0)
+    }
+}
+
+/// see Pratt::expression
+pub struct Expression<'r, 'p, T>(&'r Pratt<'p, T>, u32);
+
+impl<'a, 'r, 'p, T> Parser<'a, T> for Expression<'r, 'p, T>
+where
+    T: 'p,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        self.0.parse_bp(input, self.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{char, function, integer, string, symbol};
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    enum Expr {
+        Number(i64),
+        Unary(char, Box<Expr>),
+        Binary(char, Box<Expr>, Box<Expr>),
+        Condition(Box<Expr>, Box<Expr>, Box<Expr>),
+        Call(Box<Expr>, Vec<Expr>),
+        Index(Box<Expr>, Box<Expr>),
+    }
+
+    fn number(v: i64) -> Expr {
+        Expr::Number(v)
+    }
+
+    fn binary(c: char, v: Expr, w: Expr) -> Expr {
+        Expr::Binary(c, v.into(), w.into())
+    }
+
+    fn calculator<'a>() -> Pratt<'a, i64> {
+        pratt(integer.lexeme().map(|v: i64| v))
+            .infix_left(char('+').lexeme(), 10, |v, w| v + w)
+            .infix_left(char('-').lexeme(), 10, |v, w| v - w)
+            .infix_left(char('*').lexeme(), 20, |v, w| v * w)
+            .infix_left(char('/').lexeme(), 20, |v, w| v / w)
+            .infix_right(char('^').lexeme(), 30, |v, w| v.pow(w as u32))
+            .nud(char('(').lexeme(), |pratt, input| {
+                pratt.expression(0).left(char(')').lexeme()).parse(input)
+            })
+    }
+
+    #[test]
+    fn pratt_precedence() {
+        let parser = calculator();
+        assert_eq!(dbg!(parser.parse("1 + 2 * 3")), Some((7, "")));
+        assert_eq!(dbg!(parser.parse("(1 + 2) * 3")), Some((9, "")));
+        assert_eq!(dbg!(parser.parse("1 - 2 - 3")), Some((-4, "")));
+        assert_eq!(dbg!(parser.parse("2 ^ 3 ^ 2")), Some((512, "")));
+        assert_eq!(dbg!(parser.parse("2 * 3 ^ 2 - 1")), Some((17, "")));
+        assert_eq!(dbg!(parser.parse("abc")), None);
+    }
+
+    #[test]
+    fn pratt_backtrack_before_operator() {
+        let parser = calculator();
+        assert_eq!(dbg!(parser.parse("1 + 2 *")), Some((3, "*")));
+        assert_eq!(dbg!(parser.parse("1 + ")), Some((1, "+ ")));
+        assert_eq!(dbg!(parser.parse("(1 + 2")), None);
+    }
+
+    #[test]
+    fn pratt_reuse_table() {
+        // 表只建一次，用在很多临时的字符串上
+        let parser = calculator();
+        let logic = pratt(integer.lexeme().map(|v: i64| v))
+            .infix_left(symbol("or"), 10, |v, w| v | w)
+            .infix_left(symbol("and"), 20, |v, w| v & w);
+        for i in 0..3 {
+            let input = format!("{} * (1 + {})", i, i);
+            assert_eq!(dbg!(parser.parse(&input)), Some((i * (1 + i), "")));
+            let input = format!("{} or 4 and 6", i);
+            assert_eq!(dbg!(logic.parse(input.as_str())), Some((i | 4, "")));
+        }
+    }
+
+    #[test]
+    fn pratt_shared_prefix() {
+        // *先注册，也能匹配**的开头，失败了要接着试**
+        let parser = pratt(integer.lexeme().map(|v: i64| v))
+            .infix_left(char('*').lexeme(), 20, |v, w| v * w)
+            .infix_right(string("**").lexeme(), 30, |v, w| v.pow(w as u32));
+        assert_eq!(dbg!(parser.parse("2 ** 3 * 2")), Some((16, "")));
+        assert_eq!(dbg!(parser.parse("2 * 3 ** 2")), Some((18, "")));
+        assert_eq!(dbg!(parser.parse("2 ** 3 ** 2")), Some((512, "")));
+    }
+
+    #[test]
+    fn pratt_prefix_and_postfix() {
+        let parser = pratt(integer.lexeme().map(number))
+            .prefix(char('-').lexeme(), 25, |v| Expr::Unary('-', v.into()))
+            .postfix(char('!').lexeme(), 40, |v| Expr::Unary('!', v.into()))
+            .infix_left(char('+').lexeme(), 10, |v, w| binary('+', v, w))
+            .infix_left(char('*').lexeme(), 20, |v, w| binary('*', v, w));
+        assert_eq!(
+            dbg!(parser.parse("- 1 * 2!")),
+            Some((
+                binary(
+                    '*',
+                    Expr::Unary('-', number(1).into()),
+                    Expr::Unary('!', number(2).into())
+                ),
+                ""
+            ))
+        );
+        assert_eq!(
+            dbg!(parser.parse("-1!")),
+            Some((
+                Expr::Unary('-', Expr::Unary('!', number(1).into()).into()),
+                ""
+            ))
+        );
+    }
+
+    #[test]
+    fn pratt_ternary() {
+        let parser = pratt(integer.lexeme().map(number))
+            .ternary(char('?').lexeme(), char(':').lexeme(), 5, |u, v, w| {
+                Expr::Condition(u.into(), v.into(), w.into())
+            })
+            .infix_left(char('+').lexeme(), 10, |v, w| binary('+', v, w));
+        assert_eq!(
+            dbg!(parser.parse("1 ? 2 : 3 ? 4 : 5 + 6")),
+            Some((
+                Expr::Condition(
+                    number(1).into(),
+                    number(2).into(),
+                    Expr::Condition(
+                        number(3).into(),
+                        number(4).into(),
+                        binary('+', number(5), number(6)).into()
+                    )
+                    .into()
+                ),
+                ""
+            ))
+        );
+        assert_eq!(dbg!(parser.parse("1 ? 2")), Some((number(1), "? 2")));
+    }
+
+    #[test]
+    fn pratt_call_and_index() {
+        let parser = pratt(integer.lexeme().map(number))
+            .call(
+                char('(').lexeme(),
+                char(',').lexeme(),
+                char(')').lexeme(),
+                50,
+                |v, arguments| Expr::Call(v.into(), arguments),
+            )
+            .index(char('[').lexeme(), char(']').lexeme(), 50, |v, w| {
+                Expr::Index(v.into(), w.into())
+            })
+            .infix_left(char('+').lexeme(), 10, |v, w| binary('+', v, w));
+        assert_eq!(
+            dbg!(parser.parse("1(2, 3 + 4)[5] + 6()")),
+            Some((
+                binary(
+                    '+',
+                    Expr::Index(
+                        Expr::Call(
+                            number(1).into(),
+                            vec![number(2), binary('+', number(3), number(4))]
+                        )
+                        .into(),
+                        number(5).into()
+                    ),
+                    Expr::Call(number(6).into(), vec![])
+                ),
+                ""
+            ))
+        );
+    }
+
+    #[test]
+    fn pratt_mixfix() {
+        // if c then a else b
+        let parser = pratt(integer.lexeme().map(|v: i64| v))
+            .nud(symbol("if"), |pratt, input| {
+                let (c, input) = pratt.expression(0).parse(input)?;
+                let (a, input) = pratt
+                    .expression(0)
+                    .between(symbol("then"), symbol("else"))
+                    .parse(input)?;
+                let (b, input) = pratt.expression(0).parse(input)?;
+                Some((if c != 0 { a } else { b }, input))
+            })
+            .infix_left(string("+").lexeme(), 10, |v, w| v + w);
+        assert_eq!(
+            dbg!(parser.parse("if 0 then 1 else if 1 then 2 + 3 else 4")),
+            Some((5, ""))
+        );
+        assert_eq!(dbg!(parser.parse("1 + if 1 then 2 else 3")), Some((3, "")));
+    }
+
+    #[test]
+    fn pratt_lookahead_operator() {
+        // <和<=不冲突，<后面不能是=
+        let parser = pratt(integer.lexeme().map(|v: i64| v))
+            .infix_left(string("<=").lexeme(), 10, |v, w| (v <= w) as i64)
+            .infix_left(
+                string("<").not_followed_by(char('=')).lexeme(),
+                10,
+                |v, w| (v < w) as i64,
+            )
+            .infix_left(char('+').lexeme(), 20, |v, w| v + w);
+        assert_eq!(dbg!(parser.parse("1 < 1 + 1")), Some((1, "")));
+        assert_eq!(dbg!(parser.parse("2 <= 1 + 1")), Some((1, "")));
+        assert_eq!(dbg!(parser.parse("2 < 1 + 1")), Some((0, "")));
+    }
+
+    #[test]
+    fn pratt_function_operator() {
+        fn arrow(input: &str) -> Option<((), &str)> {
+            input.strip_prefix("->").map(|remaining| ((), remaining))
+        }
+        let parser = pratt(integer.lexeme().map(|v: i64| v))
+            .infix_right(arrow.lexeme(), 10, |v, w| v * 10 + w)
+            .infix_left(
+                function(|input| char('&').parse(input))
+                    .label("and")
+                    .lexeme(),
+                20,
+                |v, w| v & w,
+            );
+        assert_eq!(dbg!(parser.parse("1 -> 3 & 2 -> 4")), Some((34, "")));
+    }
+}
@@ -0,0 +1,447 @@
+use crate::Parser;
+use std::any::Any;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+// 存在thread local里的状态要能拷贝一份，回溯的时候好还原
+trait State: Any {
+    fn snapshot(&self) -> Box<dyn State>;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<S> State for S
+where
+    S: Any + Clone,
+{
+    fn snapshot(&self) -> Box<dyn State> {
+        Box::new(self.clone())
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+// 回溯的时候要还原状态。不在每个回溯点拷贝整个状态，而是第一次修改之前记下旧的值（undo log），
+// 回溯的时候拿回来，没改过的话什么都不用做
+struct Frame {
+    state: Box<dyn State>,
+    journal: Vec<Box<dyn State>>, // 修改之前的值
+    marks: Vec<usize>,            // 每一层进行中的attempt开始时journal有多长
+}
+
+thread_local! {
+    // parse_with_state可以嵌套，所以是个栈，最上面的是当前的状态
+    static STATES: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+// parser panic了也要把这一层弹出去，不然这个线程以后用的都是这个状态
+struct FrameGuard;
+
+impl Drop for FrameGuard {
+    fn drop(&mut self) {
+        STATES.with(|v| v.borrow_mut().pop());
+    }
+}
+
+pub(crate) fn with_state<S, R, F>(state: &mut S, f: F) -> R
+where
+    S: Clone + 'static,
+    F: FnOnce() -> R,
+{
+    STATES.with(|v| {
+        v.borrow_mut().push(Frame {
+            state: Box::new(state.clone()),
+            journal: vec![],
+            marks: vec![],
+        })
+    });
+    let guard = FrameGuard;
+    let res = f();
+    let last = STATES.with(|v| {
+        let mut states = v.borrow_mut();
+        std::mem::replace(&mut states.last_mut().unwrap().state, Box::new(()))
+    });
+    drop(guard);
+    *state = *last.into_any().downcast().unwrap();
+    res
+}
+
+// 对当前的状态做点什么，没有状态或者类型不对就是用错了，直接panic
+fn access<S, R, F>(modify: bool, f: F) -> R
+where
+    S: 'static,
+    F: FnOnce(&mut S) -> R,
+{
+    STATES.with(|v| {
+        let mut states = v.borrow_mut();
+        let frame = states
+            .last_mut()
+            .expect("state used outside parse_with_state");
+        // 最里面的attempt开始之后还没改过的话，先把现在的值记下来
+        if modify && frame.marks.last() == Some(&frame.journal.len()) {
+            let saved = frame.state.as_ref().snapshot();
+            frame.journal.push(saved);
+        }
+        let state = frame
+            .state
+            .as_mut()
+            .as_any_mut()
+            .downcast_mut()
+            .expect("state is not the type given to parse_with_state");
+        f(state)
+    })
+}
+
+// attempt结束的时候把mark弹出去，panic也一样
+struct MarkGuard {
+    mark: usize,
+    rollback: bool,
+}
+
+impl Drop for MarkGuard {
+    fn drop(&mut self) {
+        STATES.with(|v| {
+            let mut states = v.borrow_mut();
+            let frame = match states.last_mut() {
+                Some(v) => v,
+                None => return,
+            };
+            frame.marks.pop();
+            let mark = self.mark;
+            if self.rollback {
+                // journal[mark]就是attempt开始时的值
+                if frame.journal.len() > mark {
+                    frame.journal.truncate(mark + 1);
+                    frame.state = frame.journal.pop().unwrap();
+                }
+            } else if let Some(&outer) = frame.marks.last() {
+                // 外层attempt要的值在journal[outer]，里面这层记的不用留了
+                frame.journal.truncate(mark.max(outer + 1));
+            } else {
+                frame.journal.clear();
+            }
+        });
+    }
+}
+
+// 试一下f，keep说不要的话把状态还原成试之前的样子。没有状态的时候什么都不做，普通的parse不用付出代价
+fn transaction<R, F, K>(f: F, keep: K) -> R
+where
+    F: FnOnce() -> R,
+    K: FnOnce(&R) -> bool,
+{
+    let mark = STATES.with(|v| {
+        v.borrow_mut().last_mut().map(|frame| {
+            let mark = frame.journal.len();
+            frame.marks.push(mark);
+            mark
+        })
+    });
+    let mut guard = match mark {
+        Some(mark) => MarkGuard {
+            mark,
+            rollback: true, // 要是f panic了，就当失败
+        },
+        None => return f(),
+    };
+    let res = f();
+    guard.rollback = !keep(&res);
+    res
+}
+
+/// run f, undo its changes to the state if it fails
+pub(crate) fn attempt<T, F>(f: F) -> Option<T>
+where
+    F: FnOnce() -> Option<T>,
+{
+    transaction(f, Option::is_some)
+}
+
+/// like attempt, for f that hands something back on failure like pratt's led
+pub(crate) fn attempt_ok<T, E, F>(f: F) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E>,
+{
+    transaction(f, Result::is_ok)
+}
+
+/// run f, always undo its changes to the state, for parsers that don't consume input like look_ahead
+pub(crate) fn peek<R, F>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    transaction(f, |_| false)
+}
+
+#[derive(Clone)]
+pub struct GetState<S>(PhantomData<S>);
+
+impl<'a, S> Parser<'a, S> for GetState<S>
+where
+    S: Clone + 'static,
+{
+    fn parse(&self, input: &'a str) -> Option<(S, &'a str)> {
+        Some((access(false, |state: &mut S| state.clone()), input))
+    }
+}
+
+/// return a copy of the current state, consume no input
+///
+/// panic if not inside parse_with_state with the same state type.
+pub fn get_state<S>() -> GetState<S>
+where
+    S: Clone + 'static,
+{
+    GetState(PhantomData)
+}
+
+#[derive(Clone)]
+pub struct SetState<S>(S);
+
+impl<'a, S> Parser<'a, ()> for SetState<S>
+where
+    S: Clone + 'static,
+{
+    fn parse(&self, input: &'a str) -> Option<((), &'a str)> {
+        access(true, |state: &mut S| *state = self.0.clone());
+        Some(((), input))
+    }
+}
+
+/// replace the current state with state, consume no input
+pub fn set_state<S>(state: S) -> SetState<S>
+where
+    S: Clone + 'static,
+{
+    SetState(state)
+}
+
+#[derive(Clone)]
+pub struct UpdateState<S, F>(F, PhantomData<S>);
+
+impl<'a, S, F> Parser<'a, ()> for UpdateState<S, F>
+where
+    S: 'static,
+    F: Fn(&mut S),
+{
+    fn parse(&self, input: &'a str) -> Option<((), &'a str)> {
+        access(true, |state: &mut S| (self.0)(state));
+        Some(((), input))
+    }
+}
+
+/// change the current state in place with f, consume no input
+pub fn update_state<S, F>(f: F) -> UpdateState<S, F>
+where
+    S: 'static,
+    F: Fn(&mut S),
+{
+    UpdateState(f, PhantomData)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{char, digit, function, identifier, not, opt, permutation, pratt, symbol};
+    use std::collections::HashSet;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    fn counted<'a>(c: char) -> impl Parser<'a, char> {
+        char(c).left(update_state(|n: &mut usize| *n += 1))
+    }
+
+    // 返回剩下的输入和最后的状态
+    fn run<'a, T, P>(parser: &P, input: &'a str) -> (Option<&'a str>, usize)
+    where
+        P: Parser<'a, T>,
+    {
+        let mut n = 0usize;
+        let res = parser.parse_with_state(&mut n, input);
+        (res.map(|(_, remaining)| remaining), n)
+    }
+
+    #[test]
+    fn state_counter() {
+        let parser = char('a')
+            .left(update_state(|n: &mut usize| *n += 1))
+            .many()
+            .map(|_: String| ())
+            .right(get_state::<usize>());
+        let mut n = 10usize;
+        assert_eq!(
+            dbg!(parser.parse_with_state(&mut n, "aaab")),
+            Some((13, "b"))
+        );
+        assert_eq!(n, 13);
+
+        let parser = set_state(0usize).right(get_state::<usize>());
+        assert_eq!(dbg!(parser.parse_with_state(&mut n, "")), Some((0, "")));
+        assert_eq!(n, 0);
+    }
+
+    #[test]
+    fn state_rollback_on_choice() {
+        // 第一个分支改了状态之后失败了，改动不能留下来
+        let counted = |c| char(c).left(update_state(|n: &mut usize| *n += 1));
+        let parser = counted('a')
+            .left(char('x'))
+            .choice(counted('a').left(char('y')))
+            .right(get_state::<usize>());
+        let mut n = 0usize;
+        assert_eq!(dbg!(parser.parse_with_state(&mut n, "ay")), Some((1, "")));
+        assert_eq!(n, 1);
+
+        let parser = counted('a').left(char('x')).optional();
+        assert_eq!(
+            dbg!(parser.parse_with_state(&mut n, "ay")),
+            Some((None, "ay"))
+        );
+        assert_eq!(n, 1);
+    }
+
+    #[test]
+    fn state_symbol_table() {
+        let name = identifier().lexeme();
+        let declare = symbol("let")
+            .right(name.clone())
+            .left(symbol(";"))
+            .and_then(|v: &str| {
+                let v = v.to_owned();
+                update_state(move |names: &mut HashSet<String>| {
+                    names.insert(v.clone());
+                })
+            });
+        let usage = name
+            .and_then(|v: &str| get_state::<HashSet<String>>().map(move |names| names.contains(v)));
+        let parser = declare.many().map(|_: Vec<()>| ()).right(usage.many());
+
+        let mut names = HashSet::<String>::new();
+        assert_eq!(
+            dbg!(parser.parse_with_state(&mut names, "let a; let b; a c b")),
+            Some((vec![true, false, true], ""))
+        );
+        assert_eq!(names.len(), 2);
+    }
+
+    #[test]
+    fn state_nested() {
+        let inner = digit.left(update_state(|s: &mut String| s.push('!')));
+        let parser = char('a')
+            .left(update_state(|n: &mut usize| *n += 1))
+            .left(|input| {
+                let mut s = String::new();
+                inner.parse_with_state(&mut s, input)
+            })
+            .right(get_state::<usize>());
+        let mut n = 0usize;
+        assert_eq!(dbg!(parser.parse_with_state(&mut n, "a1")), Some((1, "")));
+    }
+
+    #[test]
+    fn state_rollback_in_many() {
+        let ab = || counted('a').left(char('b'));
+        assert_eq!(run::<Vec<char>, _>(&ab().many(), "abac"), (Some("ac"), 1));
+        assert_eq!(run::<Vec<char>, _>(&ab().many1(), "abac"), (Some("ac"), 1));
+        assert_eq!(
+            run::<Vec<char>, _>(&ab().repeat(0..5), "abac"),
+            (Some("ac"), 1)
+        );
+        // end先试了一次，吃了a之后失败
+        assert_eq!(
+            run::<Vec<char>, _>(&counted('a').many_till(counted('a').left(char('b'))), "aab"),
+            (Some(""), 2)
+        );
+    }
+
+    #[test]
+    fn state_rollback_in_separated() {
+        let ab = || counted('a').left(char('b'));
+        assert_eq!(
+            run(&ab().separated_by(char(',')), "ab,ac"),
+            (Some(",ac"), 1)
+        );
+        assert_eq!(
+            run(&ab().separated_by(counted(',')), "ab,ac"),
+            (Some(",ac"), 1)
+        );
+        // 分隔符留下了，它的改动也留下
+        assert_eq!(
+            run(&ab().separated_end_by(counted(',')), "ab,ac"),
+            (Some("ac"), 2)
+        );
+    }
+
+    #[test]
+    fn state_rollback_in_look_ahead() {
+        assert_eq!(run(&counted('a').look_ahead(), "a"), (Some("a"), 0));
+        assert_eq!(
+            run(&not(counted('a').left(char('b'))), "ac"),
+            (Some("ac"), 0)
+        );
+        assert_eq!(run(&not(counted('a')), "a"), (None, 0));
+    }
+
+    #[test]
+    fn state_rollback_in_chain() {
+        let operator = || counted('+').map(|_| (|v, _| v) as fn(char, char) -> char);
+        assert_eq!(
+            run(&counted('a').chain_left1(operator()), "a+a+b"),
+            (Some("+b"), 3)
+        );
+        assert_eq!(
+            run(&counted('a').chain_right1(operator()), "a+a+b"),
+            (Some("+b"), 3)
+        );
+    }
+
+    #[test]
+    fn state_rollback_in_pratt() {
+        let parser = pratt(function(|input| counted('a').map(|_| ()).parse(input)))
+            .nud(char('a'), |_, input| {
+                update_state(|n: &mut usize| *n += 1)
+                    .right(char('!'))
+                    .map(|_| ())
+                    .parse(input)
+            })
+            .led(
+                char('+'),
+                10,
+                |_, input| counted('a').left(char('b')).parse(input),
+                |v, _| v,
+            );
+        assert_eq!(run(&parser, "a"), (Some(""), 1));
+        assert_eq!(run(&parser, "a+ac"), (Some("+ac"), 1));
+        assert_eq!(run(&parser, "a+ab"), (Some(""), 2));
+    }
+
+    #[test]
+    fn state_rollback_in_permutation() {
+        let parser = permutation((counted('a').left(char('b')), opt(counted('a'))));
+        assert_eq!(run(&parser, "aab"), (Some(""), 2));
+        assert_eq!(run(&parser, "ac"), (None, 1));
+    }
+
+    #[test]
+    fn state_popped_after_panic() {
+        let mut n = 0usize;
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            counted('a')
+                .right(counted('b').map(|_| -> char { panic!("boom") }).optional())
+                .parse_with_state(&mut n, "ab")
+        }));
+        assert!(res.is_err());
+        assert!(STATES.with(|v| v.borrow().is_empty()));
+    }
+
+    #[test]
+    #[should_panic(expected = "outside parse_with_state")]
+    fn state_outside() {
+        let _ = get_state::<usize>().parse("");
+    }
+}
@@ -0,0 +1,262 @@
+use crate::position::{has_origin, position_of};
+use crate::Parser;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io::{self, Write};
+
+struct Tracer {
+    on: bool,
+    depth: usize,
+    sinks: Vec<Box<dyn Write>>, // parse_with_trace的sink，每一行马上写过去，卡住或者panic了也能看到
+    out: Box<dyn Write>,        // set_trace打开的时候写到这里，平时就是stderr
+}
+
+thread_local! {
+    static TRACER: RefCell<Tracer> = RefCell::new(Tracer {
+        on: false,
+        depth: 0,
+        sinks: vec![],
+        out: Box::new(io::stderr()),
+    });
+}
+
+/// turn trace points on or off for this thread, they log to stderr when on
+///
+/// off by default, so trace points can stay in the grammar and cost almost nothing. Positions count from origin,
+/// outside origin they are shown as `?`.
+pub fn set_trace(on: bool) {
+    TRACER.with(|v| v.borrow_mut().on = on);
+}
+
+fn enabled() -> bool {
+    TRACER.with(|v| {
+        let tracer = v.borrow();
+        tracer.on || !tracer.sinks.is_empty()
+    })
+}
+
+fn emit(line: String) {
+    TRACER.with(|v| {
+        let tracer = &mut *v.borrow_mut();
+        let out = match tracer.sinks.last_mut() {
+            Some(sink) => sink,
+            None => &mut tracer.out,
+        };
+        let _ = writeln!(out, "{}{}", "  ".repeat(tracer.depth), line);
+    });
+}
+
+#[cfg(test)]
+fn set_out(out: Box<dyn Write>) -> Box<dyn Write> {
+    TRACER.with(|v| std::mem::replace(&mut v.borrow_mut().out, out))
+}
+
+// 没有origin的话不知道自己在哪，与其从自己的输入开头算出一个错的位置，不如不写
+fn at(input: &str) -> String {
+    if has_origin() {
+        let v = position_of(input);
+        format!("{}:{}", v.line, v.column)
+    } else {
+        "?".to_owned()
+    }
+}
+
+// 剩下的输入可能很长，只显示开头一点
+fn preview(input: &str) -> String {
+    match input.char_indices().nth(16) {
+        Some((i, _)) => format!("{:?}...", &input[..i]),
+        None => format!("{:?}", input),
+    }
+}
+
+struct SinkGuard;
+
+impl Drop for SinkGuard {
+    fn drop(&mut self) {
+        TRACER.with(|v| v.borrow_mut().sinks.pop());
+    }
+}
+
+pub(crate) fn with_trace<R, F>(sink: Box<dyn Write>, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    TRACER.with(|v| v.borrow_mut().sinks.push(sink));
+    let _guard = SinkGuard;
+    f()
+}
+
+// 里面的trace往右缩进一层，panic的时候也要退回来
+struct Nested;
+
+impl Nested {
+    fn new() -> Self {
+        TRACER.with(|v| v.borrow_mut().depth += 1);
+        Nested
+    }
+}
+
+impl Drop for Nested {
+    fn drop(&mut self) {
+        TRACER.with(|v| v.borrow_mut().depth -= 1);
+    }
+}
+
+#[derive(Clone)]
+pub struct Trace<'b, P>(pub(crate) P, pub(crate) Cow<'b, str>);
+
+impl<P> Trace<'_, P> {
+    // pratt的运算符也走这里，f就是self.0.parse或者self.0.skip
+    pub(crate) fn run<'a, R, F>(&self, input: &'a str, f: F) -> Option<(R, &'a str)>
+    where
+        F: FnOnce() -> Option<(R, &'a str)>,
+    {
+        if !enabled() {
+            return f();
+        }
+
+        let start = at(input);
+        emit(format!("{} at {} {}", self.1, start, preview(input)));
+
+        let nested = Nested::new();
+        let res = f();
+        drop(nested);
+
+        match &res {
+            Some((_, remaining)) => {
+                let consumed = &input[..input.len() - remaining.len()];
+                emit(format!(
+                    "{} ok {}..{} {}",
+                    self.1,
+                    start,
+                    at(remaining),
+                    preview(consumed)
+                ));
+            }
+            None => emit(format!("{} fail at {}", self.1, start)),
+        }
+        res
+    }
+}
+
+impl<'a, 'b, T, P> Parser<'a, T> for Trace<'b, P>
+where
+    P: Parser<'a, T>,
+{
+    fn parse(&self, input: &'a str) -> Option<(T, &'a str)> {
+        self.run(input, || self.0.parse(input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{char, integer, origin, symbol, whitespaces};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    // set_trace打开的时候写到这里，测试完再看
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn expr(input: &str) -> Option<(i64, &str)> {
+        let atom = integer
+            .map(|v: i64| v)
+            .lexeme()
+            .trace("number")
+            .choice(expr.between(symbol("("), symbol(")")).trace("group"));
+        atom.chain_left1(symbol("+").map(|_| (|a, b| a + b) as fn(i64, i64) -> i64))
+            .trace("expr")
+            .parse(input)
+    }
+
+    #[test]
+    fn trace_tree() {
+        let sink = Shared::default();
+        assert_eq!(
+            dbg!(expr.parse_with_trace(sink.clone(), "1 +\n(x")),
+            Some((1, "+\n(x"))
+        );
+        assert_eq!(
+            String::from_utf8(sink.0.take()).unwrap(),
+            r#"expr at 1:1 "1 +\n(x"
+  number at 1:1 "1 +\n(x"
+  number ok 1:1..1:3 "1 "
+  number at 2:1 "(x"
+  number fail at 2:1
+  group at 2:1 "(x"
+    expr at 2:2 "x"
+      number at 2:2 "x"
+      number fail at 2:2
+      group at 2:2 "x"
+      group fail at 2:2
+    expr fail at 2:2
+  group fail at 2:1
+expr ok 1:1..1:3 "1 "
+"#
+        );
+    }
+
+    #[test]
+    fn trace_global() {
+        let out = Shared::default();
+        let stderr = set_out(Box::new(out.clone()));
+        set_trace(true);
+        let parser = || whitespaces.right(char('x').trace("x"));
+        assert_eq!(dbg!(origin(parser()).parse("\n\n   x")), Some(('x', "")));
+        // 没有origin就不知道位置
+        assert_eq!(dbg!(parser().parse("\n\n   x")), Some(('x', "")));
+        set_trace(false);
+        assert_eq!(dbg!(parser().parse("x")), Some(('x', "")));
+        set_out(stderr);
+
+        assert_eq!(
+            String::from_utf8(out.0.take()).unwrap(),
+            r#"x at 3:4 "x"
+x ok 3:4..3:5 "x"
+x at ? "x"
+x ok ?..? "x"
+"#
+        );
+    }
+
+    #[test]
+    fn trace_written_before_panic() {
+        let sink = Shared::default();
+        let parser = char('a')
+            .trace("a")
+            .map(|_| -> char { panic!("boom") })
+            .trace("outer");
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            parser.parse_with_trace(sink.clone(), "ab")
+        }));
+        assert!(res.is_err());
+        // panic之前的行已经写出去了
+        assert_eq!(
+            String::from_utf8(sink.0.take()).unwrap(),
+            r#"outer at 1:1 "ab"
+  a at 1:1 "ab"
+  a ok 1:1..1:2 "a"
+"#
+        );
+        assert!(TRACER.with(|v| v.borrow().sinks.is_empty()));
+        assert_eq!(TRACER.with(|v| v.borrow().depth), 0);
+    }
+
+    #[test]
+    fn trace_off() {
+        // 没有打开的时候什么都不记，结果也不变
+        assert_eq!(dbg!(expr.parse("(1 + 2) + 3")), Some((6, "")));
+        assert_eq!(preview("0123456789abcdefghij"), "\"0123456789abcdef\"...");
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{cst, string, Left, Parser, Str};
+use std::borrow::Cow;
+
+#[derive(Clone)]
+pub struct LineComment<'b>(Cow<'b, str>);
+
+impl<'a, 'b> Parser<'a, &'a str> for LineComment<'b> {
+    fn parse(&self, input: &'a str) -> Option<(&'a str, &'a str)> {
+        crate::budget::primitive(|| {
+            if !input.starts_with(self.0.as_ref()) {
+                return None;
+            }
+
+            // 换行符留给后面，换行有意义的文法还要用到它
+            let end = input.find('\n').unwrap_or(input.len());
+            if end == 0 {
+                return None; // 前缀是空的时候什么都没吃，trivia和many会一直转下去
+            }
+            Some(input.split_at(end))
+        })
+    }
+}
+
+/// prefix and everything after it until the end of line, return the whole comment. The newline is not consumed.
+///
+/// an empty comment (empty prefix at the end of a line) doesn't match.
+pub fn line_comment<'b, S>(prefix: S) -> LineComment<'b>
+where
+    S: Into<Cow<'b, str>>,
+{
+    LineComment(prefix.into())
+}
+
+#[derive(Clone)]
+pub struct BlockComment<'b>(Cow<'b, str>, Cow<'b, str>, bool);
+
+impl<'a, 'b> Parser<'a, &'a str> for BlockComment<'b> {
+    fn parse(&self, input: &'a str) -> Option<(&'a str, &'a str)> {
+        crate::budget::primitive(|| {
+            let (open, close) = (self.0.as_ref(), self.1.as_ref());
+            let mut remaining = input.strip_prefix(open)?;
+            let mut depth = 1;
+
+            while depth > 0 {
+                if let Some(tail) = remaining.strip_prefix(close) {
+                    depth -= 1;
+                    remaining = tail;
+                } else if let Some(tail) = remaining.strip_prefix(open).filter(|_| self.2) {
+                    depth += 1;
+                    remaining = tail;
+                } else {
+                    let c = remaining.chars().next()?; // 没有闭合就失败
+                    remaining = &remaining[c.len_utf8()..];
+                }
+            }
+
+            if remaining.len() == input.len() {
+                return None;
+            }
+            Some(input.split_at(input.len() - remaining.len()))
+        })
+    }
+}
+
+/// open, anything, then close, return the whole comment
+///
+/// if nested, `/* a /* b */ c */` is one comment. Fail if the comment is not closed.
+pub fn block_comment<'b, S1, S2>(open: S1, close: S2, nested: bool) -> BlockComment<'b>
+where
+    S1: Into<Cow<'b, str>>,
+    S2: Into<Cow<'b, str>>,
+{
+    BlockComment(open.into(), close.into(), nested)
+}
+
+/// what lexeme and symbol skip after a token: whitespaces and comments
+#[derive(Clone)]
+pub struct Trivia<'b> {
+    newlines: bool,
+    line_comments: Vec<LineComment<'b>>,
+    block_comments: Vec<BlockComment<'b>>,
+}
+
+/// skip 0 or more whitespaces, and nothing else until configured otherwise
+pub fn trivia<'b>() -> Trivia<'b> {
+    Trivia {
+        newlines: true,
+        line_comments: vec![],
+        block_comments: vec![],
+    }
+}
+
+impl<'b> Trivia<'b> {
+    /// whether to skip \n and \r too, turn this off when newlines are significant
+    pub fn newlines(self, newlines: bool) -> Self {
+        Self { newlines, ..self }
+    }
+
+    /// also skip comments like line_comment(prefix)
+    pub fn line_comment<S>(mut self, prefix: S) -> Self
+    where
+        S: Into<Cow<'b, str>>,
+    {
+        self.line_comments.push(line_comment(prefix));
+        self
+    }
+
+    /// also skip comments like block_comment(open, close, nested)
+    pub fn block_comment<S1, S2>(mut self, open: S1, close: S2, nested: bool) -> Self
+    where
+        S1: Into<Cow<'b, str>>,
+        S2: Into<Cow<'b, str>>,
+    {
+        self.block_comments.push(block_comment(open, close, nested));
+        self
+    }
+
+    fn skip_whitespaces<'a>(&self, input: &'a str) -> &'a str {
+        let remaining = if self.newlines {
+            input.trim_start()
+        } else {
+            input.trim_start_matches(|c: char| c.is_whitespace() && c != '\n' && c != '\r')
+        };
+        cst::token(|| cst::WHITESPACE.into(), input, remaining);
+        remaining
+    }
+}
+
+impl<'a, 'b> Parser<'a, ()> for Trivia<'b> {
+    fn parse(&self, input: &'a str) -> Option<((), &'a str)> {
+        crate::budget::primitive(|| {
+            let mut input = self.skip_whitespaces(input);
+
+            // 空格和注释交替出现，直到两个都吃不动为止
+            'outer: loop {
+                for comment in self.line_comments.iter() {
+                    if let Some((_, remaining)) = comment.parse(input) {
+                        cst::token(|| cst::COMMENT.into(), input, remaining);
+                        input = self.skip_whitespaces(remaining);
+                        continue 'outer;
+                    }
+                }
+
+                for comment in self.block_comments.iter() {
+                    if let Some((_, remaining)) = comment.parse(input) {
+                        cst::token(|| cst::COMMENT.into(), input, remaining);
+                        input = self.skip_whitespaces(remaining);
+                        continue 'outer;
+                    }
+                }
+
+                break Some(((), input));
+            }
+        })
+    }
+}
+
+/// lexeme and symbol that skip trivia instead of just whitespaces
+#[derive(Clone)]
+pub struct Lexer<'b>(Trivia<'b>);
+
+/// build lexeme and symbol that skip trivia
+// Trivia自己就是Parser，如果把lexeme写成Trivia的方法，会和Parser::lexeme撞名字
+pub fn lexer(trivia: Trivia) -> Lexer {
+    Lexer(trivia)
+}
+
+impl<'b> Lexer<'b> {
+    /// match p and then skip trivia, return what p matches
+    pub fn lexeme<'a, T, P>(&self, p: P) -> Left<T, P, (), Trivia<'b>>
+    where
+        P: Parser<'a, T>,
+    {
+        p.lexeme_with(self.0.clone())
+    }
+
+    /// match a particular string and then skip trivia
+    pub fn symbol<'a, S>(&self, s: S) -> Left<&'a str, Str<'b>, (), Trivia<'b>>
+    where
+        S: Into<Cow<'b, str>>,
+    {
+        self.lexeme(string(s))
+    }
+
+    /// the trivia itself, to skip leading trivia before the first token
+    pub fn trivia(&self) -> Trivia<'b> {
+        self.0.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{char, integer, newline};
+
+    #[test]
+    fn line_comment_until_newline() {
+        let parser = line_comment("#");
+        assert_eq!(dbg!(parser.parse("# 注释\n1")), Some(("# 注释", "\n1")));
+        assert_eq!(dbg!(parser.parse("#")), Some(("#", "")));
+        assert_eq!(dbg!(parser.parse("1 # a")), None);
+    }
+
+    #[test]
+    fn empty_comment_prefix() {
+        let parser = line_comment("");
+        assert_eq!(dbg!(parser.parse("abc\n")), Some(("abc", "\n")));
+        assert_eq!(dbg!(parser.parse("\nabc")), None);
+        assert_eq!(dbg!(block_comment("", "", false).parse("abc")), None);
+
+        // 以前这里会死循环
+        let parser = trivia().newlines(false).line_comment("");
+        assert_eq!(dbg!(parser.parse("  x\n\ny")), Some(((), "\n\ny")));
+        let parser = trivia()
+            .newlines(false)
+            .line_comment("")
+            .right(newline)
+            .many();
+        assert_eq!(dbg!(parser.parse("a\n\nb")), Some(("\n\n".to_owned(), "b")));
+    }
+
+    #[test]
+    fn block_comment_nested() {
+        let parser = block_comment("/*", "*/", true);
+        assert_eq!(
+            dbg!(parser.parse("/* a /* b */ c */d")),
+            Some(("/* a /* b */ c */", "d"))
+        );
+        assert_eq!(dbg!(parser.parse("/* a /* b */ c")), None);
+
+        let parser = block_comment("/*", "*/", false);
+        assert_eq!(
+            dbg!(parser.parse("/* a /* b */ c */d")),
+            Some(("/* a /* b */", " c */d"))
+        );
+        assert_eq!(dbg!(parser.parse("/* λ")), None);
+    }
+
+    #[test]
+    fn trivia_lexeme() {
+        let lexer = lexer(trivia().line_comment("#").block_comment("/*", "*/", true));
+        let parser = lexer.trivia().right(
+            lexer
+                .lexeme(integer.map(|v: i64| v))
+                .separated_by(lexer.symbol(",")),
+        );
+        assert_eq!(
+            dbg!(parser.parse(" # zero\n1 # one\n, /* two /* 2 */ */ 2 ,\n\t3 /* unterminated")),
+            Some((vec![1, 2, 3], "/* unterminated"))
+        );
+
+        let parser = integer
+            .map(|v: i64| v)
+            .lexeme_with(trivia().line_comment("//"))
+            .many();
+        assert_eq!(
+            dbg!(parser.parse("1 // a\n // b\n2")),
+            Some((vec![1, 2], ""))
+        );
+    }
+
+    #[test]
+    fn trivia_significant_newlines() {
+        let lexer = lexer(trivia().newlines(false).line_comment("#"));
+        let line = lexer.lexeme(integer.map(|v: i64| v)).many().left(newline);
+        let parser = line.many();
+        assert_eq!(
+            dbg!(parser.parse("1 2 # a\n3\t4\n")),
+            Some((vec![vec![1, 2], vec![3, 4]], ""))
+        );
+        assert_eq!(
+            dbg!(lexer.symbol("x").left(char('\n')).parse("x  \n")),
+            Some(("x", ""))
+        );
+    }
+}